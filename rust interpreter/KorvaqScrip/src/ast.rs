@@ -1,26 +1,232 @@
+use crate::span::Span;
+use num_bigint::BigInt;
+
 #[derive(Debug, Clone)]
 pub enum ASTNode {
-    VariableDeclaration { name: String, is_constant: bool, value: Box<ASTNode> },
-    ShowStatement { value: Box<ASTNode> },
-    Value { value: String },
-    ValueBool { value: bool },
-    ValueNum { value: f64 },
-    Variable { name: String },
-    Identifier { name: String },
+    VariableDeclaration {
+        name: String,
+        is_constant: bool,
+        value: Box<ASTNode>,
+        #[allow(dead_code)] // Read by documentation tooling, not the interpreter
+        doc: Option<String>, // Text of the `///` comments written above a `let` or `make`
+        span: Span,
+    },
+    ShowStatement { value: Box<ASTNode>, span: Span },
+    Value { value: String, span: Span },
+    ValueBool { value: bool, span: Span },
+    // Integer literals are `ValueInt` when they fit in 64 bits, `ValueBigInt` otherwise
+    ValueInt { value: i64, span: Span },
+    ValueBigInt { value: BigInt, span: Span },
+    ValueFloat { value: f64, span: Span },
+    // Backtick string, `parts` are text (`Value`) and interpolated expressions in order
+    Template { parts: Vec<ASTNode>, span: Span },
+    Variable { name: String, span: Span },
+    #[allow(dead_code)]
+    Identifier { name: String, span: Span },
     BinaryOperation {
         left: Box<ASTNode>,
         operator: String,
         right: Box<ASTNode>,
+        span: Span,
+    },
+    // Prefix `!` or `-`
+    UnaryOperation {
+        operator: String,
+        operand: Box<ASTNode>,
+        span: Span,
     },
-    DelVar { name: String },
+    // `name = value`, changes the nearest variable called `name`
+    Assignment {
+        name: String,
+        value: Box<ASTNode>,
+        span: Span,
+    },
+    DelVar { name: String, span: Span },
+    DelFunc { name: String, span: Span },
+    // `alternative` is a `Block` for `else`, or another `IfStatement` for `else if`
     IfStatement {
         condition: Box<ASTNode>,
         consequent: Box<ASTNode>,
         alternative: Option<Box<ASTNode>>,
+        span: Span,
+    },
+    WhileLoop {
+        condition: Box<ASTNode>,
+        body: Box<ASTNode>,
+        span: Span,
+    },
+    // `loop (variable, start, end, step) { ... }`, counting up to and including `end`
+    CountedLoop {
+        variable: String,
+        start: Box<ASTNode>,
+        end: Box<ASTNode>,
+        step: Option<Box<ASTNode>>,
+        body: Box<ASTNode>,
+        span: Span,
+    },
+    // `for variable in iterable { ... }`
+    ForInLoop {
+        variable: String,
+        iterable: Box<ASTNode>,
+        body: Box<ASTNode>,
+        span: Span,
+    },
+    // `start..end`, the numbers from `start` up to but not including `end`
+    Range {
+        start: Box<ASTNode>,
+        end: Box<ASTNode>,
+        span: Span,
+    },
+    FunctionDeclaration {
+        name: String,
+        params: Vec<String>,
+        body: Box<ASTNode>,
+        #[allow(dead_code)] // Read by documentation tooling, not the interpreter
+        doc: Option<String>,
+        span: Span,
     },
+    // `func(a, b) { ... }` used as a value. `name` is filled in when it is
+    // assigned straight to a variable, `let add = func(a, b) { ... }`.
+    FunctionExpression {
+        name: Option<String>,
+        params: Vec<String>,
+        body: Box<ASTNode>,
+        span: Span,
+    },
+    Return { value: Option<Box<ASTNode>>, span: Span },
+    // `callee(arguments)`, optionally written as `call name(arguments)`.
+    // `optional` is set after a `?.` in the same chain, `a?.f()`, and a
+    // void callee then gives void instead of an error.
+    Call {
+        callee: Box<ASTNode>,
+        arguments: Vec<ASTNode>,
+        optional: bool,
+        span: Span,
+    },
+    // `try { ... } catch (error_name) { ... } finally { ... }`, at least one
+    // of `handler` and `finally` is there
+    TryStatement {
+        body: Box<ASTNode>,
+        error_name: Option<String>,
+        handler: Option<Box<ASTNode>>,
+        finally: Option<Box<ASTNode>>,
+        span: Span,
+    },
+    Throw { value: Box<ASTNode>, span: Span },
+    Break { span: Span },
+    Continue { span: Span },
     Block {
         statements: Vec<ASTNode>,
+        span: Span,
+    },
+    // `uppercase text` and `lowercase text`
+    Uppercase { expr: Box<ASTNode>, span: Span },
+    Lowercase { expr: Box<ASTNode>, span: Span },
+    ArrayLiteral { elements: Vec<ASTNode>, span: Span },
+    // `{ "key": value, key2: value }`, keys in the order they were written
+    ObjectLiteral { entries: Vec<(String, ASTNode)>, span: Span },
+    // `object[index]`, `object.key` is parsed as `object["key"]`. With
+    // `optional` it was written `object?.key` or `object?.[index]`, or comes
+    // after one of those in the same chain, and a void object, a missing
+    // key or an index past the end gives void instead of an error. That
+    // way `a?.b.c` is void as soon as `a` or `a.b` is.
+    Index {
+        object: Box<ASTNode>,
+        index: Box<ASTNode>,
+        optional: bool,
+        span: Span,
+    },
+    // `object[index] = value`
+    IndexAssignment {
+        object: Box<ASTNode>,
+        index: Box<ASTNode>,
+        value: Box<ASTNode>,
+        span: Span,
+    },
+    // `arradd array value`
+    ArrayAdd {
+        array: Box<ASTNode>,
+        value: Box<ASTNode>,
+        span: Span,
+    },
+    // `arrdel array value`, where a number is an index and anything else a value to look for
+    ArrayDelete {
+        array: Box<ASTNode>,
+        value: Box<ASTNode>,
+        span: Span,
+    },
+    // `arrsize array`
+    ArraySize { array: Box<ASTNode>, span: Span },
+    // The `void` literal
+    Void { span: Span },
+    // `isvoid value`
+    IsVoid { value: Box<ASTNode>, span: Span },
+    // `tojson value`, or `prettyjson value` when `pretty` is set
+    ToJson {
+        value: Box<ASTNode>,
+        pretty: bool,
+        span: Span,
+    },
+    // `parjson text`
+    ParseJson { text: Box<ASTNode>, span: Span },
+    // `delkey object.key` or `delkey object["key"]`
+    DeleteKey {
+        object: Box<ASTNode>,
+        key: Box<ASTNode>,
+        span: Span,
     },
-    Uppercase { expr: Box<ASTNode> },
-    Lowercase { expr: Box<ASTNode> },
-}
\ No newline at end of file
+    // Placeholder for a statement that failed to parse
+    Error { span: Span },
+}
+
+impl ASTNode {
+    // The source range this node was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            ASTNode::VariableDeclaration { span, .. }
+            | ASTNode::ShowStatement { span, .. }
+            | ASTNode::Value { span, .. }
+            | ASTNode::ValueBool { span, .. }
+            | ASTNode::ValueInt { span, .. }
+            | ASTNode::ValueBigInt { span, .. }
+            | ASTNode::ValueFloat { span, .. }
+            | ASTNode::Template { span, .. }
+            | ASTNode::Variable { span, .. }
+            | ASTNode::Identifier { span, .. }
+            | ASTNode::BinaryOperation { span, .. }
+            | ASTNode::UnaryOperation { span, .. }
+            | ASTNode::Assignment { span, .. }
+            | ASTNode::DelVar { span, .. }
+            | ASTNode::DelFunc { span, .. }
+            | ASTNode::IfStatement { span, .. }
+            | ASTNode::WhileLoop { span, .. }
+            | ASTNode::CountedLoop { span, .. }
+            | ASTNode::ForInLoop { span, .. }
+            | ASTNode::Range { span, .. }
+            | ASTNode::FunctionDeclaration { span, .. }
+            | ASTNode::FunctionExpression { span, .. }
+            | ASTNode::Return { span, .. }
+            | ASTNode::Call { span, .. }
+            | ASTNode::TryStatement { span, .. }
+            | ASTNode::Throw { span, .. }
+            | ASTNode::Break { span }
+            | ASTNode::Continue { span }
+            | ASTNode::Block { span, .. }
+            | ASTNode::Uppercase { span, .. }
+            | ASTNode::Lowercase { span, .. }
+            | ASTNode::ArrayLiteral { span, .. }
+            | ASTNode::Index { span, .. }
+            | ASTNode::IndexAssignment { span, .. }
+            | ASTNode::ArrayAdd { span, .. }
+            | ASTNode::ArrayDelete { span, .. }
+            | ASTNode::ArraySize { span, .. }
+            | ASTNode::ObjectLiteral { span, .. }
+            | ASTNode::DeleteKey { span, .. }
+            | ASTNode::Void { span }
+            | ASTNode::IsVoid { span, .. }
+            | ASTNode::ToJson { span, .. }
+            | ASTNode::ParseJson { span, .. }
+            | ASTNode::Error { span } => *span,
+        }
+    }
+}
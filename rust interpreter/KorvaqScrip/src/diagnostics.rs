@@ -0,0 +1,193 @@
+use std::fmt;
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// Text attached to a region of the source. The primary label marks where the
+// problem is, secondary labels point at related code.
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+// A single problem found in the source, shared by the lexer, parser and interpreter
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub labels: Vec<Label>, // The first label is always the primary one
+    pub help: Vec<String>,  // "help:" notes printed below the snippet
+    pub notes: Vec<String>, // "note:" lines printed after the help
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            labels: vec![Label { span, message: String::new(), primary: true }],
+            help: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message, span)
+        }
+    }
+
+    // Where the problem is
+    pub fn span(&self) -> Span {
+        self.labels[0].span
+    }
+
+    // Set the text printed next to the primary caret underline
+    pub fn with_label(mut self, message: impl Into<String>) -> Self {
+        self.labels[0].message = message.into();
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label { span, message: message.into(), primary: false });
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    // Render the diagnostic the way it is shown to the user:
+    //
+    // error[E0100]: Expected ')'
+    //  --> <repl>:1:12
+    //   |
+    // 1 | show (1 + 2
+    //   |      - unclosed '(' opened here
+    //   |            ^ expected ')'
+    //
+    // Every label gets its own underline row below the line it points at.
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+
+        // Draw the underlines in source order so they are grouped by line
+        let mut labels: Vec<&Label> = self.labels.iter().collect();
+        labels.sort_by_key(|label| label.span.start);
+
+        let gutter = labels.iter().map(|label| label.span.line).max().unwrap_or(1).to_string().len();
+        let pad = " ".repeat(gutter);
+
+        let span = self.span();
+        out.push_str(&format!("{}--> {}:{}:{}\n", pad, file_name, span.line, span.column));
+        out.push_str(&format!("{} |\n", pad));
+
+        let mut last_line = None;
+        for label in labels {
+            let span = label.span;
+            let (line_start, line_text) = line_at(source, span.start);
+
+            if last_line != Some(span.line) {
+                out.push_str(&format!("{:>width$} | {}\n", span.line, line_text, width = gutter));
+                last_line = Some(span.line);
+            }
+
+            // Offsets are in bytes, the underline is in characters
+            let start = span.start.saturating_sub(line_start).min(line_text.len());
+            let end = span.end.saturating_sub(line_start).clamp(start, line_text.len());
+            let indent = line_text[..start].chars().count();
+            let width = line_text[start..end].chars().count().max(1);
+
+            let marker = if label.primary { "^" } else { "-" };
+            let mut underline = format!("{}{}", " ".repeat(indent), marker.repeat(width));
+            if !label.message.is_empty() {
+                underline.push(' ');
+                underline.push_str(&label.message);
+            }
+            out.push_str(&format!("{} | {}\n", pad, underline));
+        }
+
+        for help in &self.help {
+            out.push_str(&format!("{} = help: {}\n", pad, help));
+        }
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", pad, note));
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]: {} at {}", self.severity, self.code, self.message, self.span())
+    }
+}
+
+// Find the line containing byte offset `pos`, returning its start offset and text
+fn line_at(source: &str, pos: usize) -> (usize, &str) {
+    let mut pos = pos.min(source.len());
+    while !source.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    let start = source[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let end = source[pos..].find('\n').map_or(source.len(), |i| pos + i);
+    (start, source[start..end].trim_end_matches('\r'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_with_labels_and_help() {
+        let source = "let x = 1\nshow café + y\n";
+        let diagnostic = Diagnostic::error("E0200", "Variable 'y' not found", Span::new(23, 24, 2, 13))
+            .with_label("not defined")
+            .with_secondary(Span::new(4, 5, 1, 5), "did you mean this?")
+            .with_help("declare it with `let y = ...`");
+        let expected = "\
+error[E0200]: Variable 'y' not found
+ --> main.kq:2:13
+  |
+1 | let x = 1
+  |     - did you mean this?
+2 | show café + y
+  |             ^ not defined
+  = help: declare it with `let y = ...`
+";
+        assert_eq!(diagnostic.render("main.kq", source), expected);
+    }
+
+    #[test]
+    fn underline_counts_characters() {
+        let diagnostic = Diagnostic::warning("W0100", "Unused", Span::new(5, 10, 1, 6)).with_note("just a note");
+        let rendered = diagnostic.render("<repl>", "show café");
+        assert!(rendered.starts_with("warning[W0100]: Unused\n"));
+        assert!(rendered.contains("  |      ^^^^\n"), "{}", rendered);
+        assert!(rendered.ends_with("  = note: just a note\n"));
+    }
+}
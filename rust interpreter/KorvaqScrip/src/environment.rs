@@ -0,0 +1,97 @@
+use crate::interpreter::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// A variable together with how it was declared
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub value: Value,
+    pub is_constant: bool,
+    pub line: usize, // Line of the `let`, `make` or assignment that created it
+}
+
+// Why a variable could not be changed
+#[derive(Debug)]
+pub enum BindingError {
+    NotFound,
+    Constant { line: usize },
+}
+
+// One scope of variables. Blocks and function calls get their own scope whose
+// parent is the scope they run in, lookups walk up the chain.
+#[derive(Debug)]
+pub struct Environment {
+    values: HashMap<String, Binding>,
+    parent: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new(parent: Option<Rc<RefCell<Environment>>>) -> Rc<RefCell<Environment>> {
+        Rc::new(RefCell::new(Environment { values: HashMap::new(), parent }))
+    }
+
+    // Value of the nearest variable called `name`
+    pub fn get(&self, name: &str) -> Option<Value> {
+        match self.values.get(name) {
+            Some(binding) => Some(binding.value.clone()),
+            None => self.parent.as_ref()?.borrow().get(name),
+        }
+    }
+
+    // The variable called `name` in this scope only
+    pub fn local(&self, name: &str) -> Option<&Binding> {
+        self.values.get(name)
+    }
+
+    // Create a variable in this scope, replacing one with the same name
+    pub fn define(&mut self, name: String, binding: Binding) {
+        self.values.insert(name, binding);
+    }
+
+    // Add a variable to the outermost scope, the globals
+    pub fn define_global(&mut self, name: String, binding: Binding) {
+        match self.parent {
+            Some(ref parent) => parent.borrow_mut().define_global(name, binding),
+            None => self.define(name, binding),
+        }
+    }
+
+    // Change the nearest variable called `name`
+    pub fn assign(&mut self, name: &str, value: Value) -> Result<(), BindingError> {
+        match self.values.get_mut(name) {
+            Some(binding) if binding.is_constant => Err(BindingError::Constant { line: binding.line }),
+            Some(binding) => {
+                binding.value = value;
+                Ok(())
+            }
+            None => match self.parent {
+                Some(ref parent) => parent.borrow_mut().assign(name, value),
+                None => Err(BindingError::NotFound),
+            },
+        }
+    }
+
+    // Delete the nearest variable called `name`
+    pub fn remove(&mut self, name: &str) -> Result<(), BindingError> {
+        match self.values.get(name) {
+            Some(binding) if binding.is_constant => Err(BindingError::Constant { line: binding.line }),
+            Some(_) => {
+                self.values.remove(name);
+                Ok(())
+            }
+            None => match self.parent {
+                Some(ref parent) => parent.borrow_mut().remove(name),
+                None => Err(BindingError::NotFound),
+            },
+        }
+    }
+
+    // Delete every mutable variable in this scope and the ones around it
+    pub fn remove_all_mutable(&mut self) {
+        self.values.retain(|_, binding| binding.is_constant);
+        if let Some(ref parent) = self.parent {
+            parent.borrow_mut().remove_all_mutable();
+        }
+    }
+}
@@ -1,87 +1,443 @@
 use crate::ast::ASTNode;
+use crate::diagnostics::Diagnostic;
+use crate::environment::{Binding, BindingError, Environment};
+use crate::numbers;
+use crate::span::Span;
+use crate::strings;
+use indexmap::IndexMap;
+use num_bigint::BigInt;
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Deepest nesting of function calls before giving up with "stack overflow"
+const MAX_CALL_DEPTH: usize = 1000;
 
 #[derive(Debug, Clone)]
 pub enum Value {
     String(String),
-    Number(f64), 
+    Int(i64),
+    BigInt(BigInt), // An integer too big for `Int`, see `numbers`
+    Float(f64),
     Boolean(bool),
+    Function(Rc<Function>), // A function from the function table
+    Closure(Rc<Function>),  // An anonymous function created by `func(...) { ... }`
+    Array(Rc<RefCell<Vec<Value>>>), // Shared, changes through one variable show up in every other
+    Object(Rc<RefCell<IndexMap<String, Value>>>), // Shared like arrays, keys keep the order they were added in
+    Null, // No value, JSON `null`
+    Error(Rc<RuntimeError>), // A caught error, or one made with `error(kind, message)`
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        values_equal(self, other, &mut Vec::new())
+    }
+}
+
+// Arrays and objects can hold themselves, `arradd a a` or `o.self = o`.
+// `comparing` holds the pairs of arrays and objects already being compared
+// further out, meeting a pair again means
+// both sides repeat the same way from there, so that part is equal.
+fn values_equal(left: &Value, right: &Value, comparing: &mut Vec<(*const (), *const ())>) -> bool {
+    match (left, right) {
+        (Value::String(a), Value::String(b)) => a == b,
+        (a, b) if numbers::is_number(a) && numbers::is_number(b) => numbers::equals(a, b),
+        (Value::Boolean(a), Value::Boolean(b)) => a == b,
+        (Value::Function(a), Value::Function(b)) | (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+        (Value::Array(a), Value::Array(b)) => {
+            let pair = (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ());
+            if Rc::ptr_eq(a, b) || comparing.contains(&pair) {
+                return true;
+            }
+            comparing.push(pair);
+            let (a, b) = (a.borrow(), b.borrow());
+            let equal = a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| values_equal(a, b, comparing));
+            comparing.pop();
+            equal
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            let pair = (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ());
+            if Rc::ptr_eq(a, b) || comparing.contains(&pair) {
+                return true;
+            }
+            comparing.push(pair);
+            let (a, b) = (a.borrow(), b.borrow());
+            let equal = a.len() == b.len()
+                && a.iter().all(|(key, a)| b.get(key).is_some_and(|b| values_equal(a, b, comparing)));
+            comparing.pop();
+            equal
+        }
+        (Value::Null, Value::Null) => true,
+        (Value::Error(a), Value::Error(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+// A function declared with `func`, or one the interpreter provides
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: FunctionBody,
+}
+
+pub enum FunctionBody {
+    // `body` is always a `Block`, `env` is the scope the function was created
+    // in, which the function keeps alive and can see when it runs
+    User { body: ASTNode, env: Rc<RefCell<Environment>> },
+    Builtin(fn(&mut Interpreter, Vec<Value>, Span) -> Result<Value, RuntimeError>),
+}
+
+impl Function {
+    pub(crate) fn builtin(name: &str, params: &[&str], run: fn(&mut Interpreter, Vec<Value>, Span) -> Result<Value, RuntimeError>) -> Self {
+        Function {
+            name: name.to_string(),
+            params: params.iter().map(|param| param.to_string()).collect(),
+            body: FunctionBody::Builtin(run),
+        }
+    }
+
+    fn is_builtin(&self) -> bool {
+        matches!(self.body, FunctionBody::Builtin(_))
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<func {}/{}>", self.name, self.params.len())
+    }
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Value::String(val) => write!(f, "{}", val),
-            Value::Number(val) => write!(f, "{}", val),
+            Value::Int(val) => write!(f, "{}", val),
+            Value::BigInt(val) => write!(f, "{}", val),
+            // Whole floats keep their `.0`, so `2.0` doesn't look like the integer `2`
+            Value::Float(val) if val.is_finite() && val.fract() == 0.0 => write!(f, "{:.1}", val),
+            Value::Float(val) => write!(f, "{}", val),
             Value::Boolean(val) => write!(f, "{}", val),
+            Value::Function(function) | Value::Closure(function) => write!(f, "{:?}", function),
+            Value::Null => write!(f, "void"),
+            Value::Error(error) => write!(f, "{}: {}", error.kind, error.message),
+            // Arrays and objects are laid out like JSON, `["a",1,{"b":true}]`
+            Value::Array(_) | Value::Object(_) => write_nested(f, self, &mut Vec::new()),
         }
     }
 }
 
+// Longest value `describe` writes out in full, longer ones are cut short
+const MAX_DESCRIBED_CHARS: usize = 40;
+
+impl Value {
+    // The name of the value's type as a script sees it
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Int(_) | Value::BigInt(_) => "int",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Function(_) | Value::Closure(_) => "function",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+            Value::Null => "void",
+            Value::Error(_) => "error",
+        }
+    }
+
+    // The value as error messages mention it, its type and then the value,
+    // `int 3`, `string "x"` or just `void`
+    pub(crate) fn describe(&self) -> String {
+        let text = match self {
+            Value::Null => return "void".to_string(),
+            Value::Function(function) | Value::Closure(function) => return format!("function {}", function.name),
+            Value::String(text) => serde_json::Value::from(text.as_str()).to_string(),
+            other => other.to_string(),
+        };
+        if text.chars().count() > MAX_DESCRIBED_CHARS {
+            let short: String = text.chars().take(MAX_DESCRIBED_CHARS).collect();
+            format!("{} {}...", self.type_name(), short)
+        } else {
+            format!("{} {}", self.type_name(), text)
+        }
+    }
+}
+
+// Strings inside arrays and objects are quoted, so `["1"]` and `[1]` look
+// different. `open` holds the arrays and objects being written around
+// `value`, one that holds itself is written as `[...]` or `{...}` where it
+// comes round again.
+fn write_nested(f: &mut fmt::Formatter, value: &Value, open: &mut Vec<*const ()>) -> fmt::Result {
+    match value {
+        Value::String(text) => write!(f, "{}", serde_json::Value::from(text.as_str())),
+        Value::Array(elements) => {
+            let id = Rc::as_ptr(elements) as *const ();
+            if open.contains(&id) {
+                return write!(f, "[...]");
+            }
+            open.push(id);
+            write!(f, "[")?;
+            for (i, element) in elements.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write_nested(f, element, open)?;
+            }
+            open.pop();
+            write!(f, "]")
+        }
+        Value::Object(entries) => {
+            let id = Rc::as_ptr(entries) as *const ();
+            if open.contains(&id) {
+                return write!(f, "{{...}}");
+            }
+            open.push(id);
+            write!(f, "{{")?;
+            for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{}:", serde_json::Value::from(key.as_str()))?;
+                write_nested(f, value, open)?;
+            }
+            open.pop();
+            write!(f, "}}")
+        }
+        other => write!(f, "{}", other),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub kind: String, // `TypeError`, `NameError`, ... or the kind given to `error(kind, message)`
+    pub message: String,
+    pub span: Span,
+    pub trace: Vec<(String, Span)>, // Each function the error escaped from and where it was called, innermost first
+    pub payload: Option<Box<Value>>, // What `throw` was given when it wasn't an error, handed back as is by `catch`
+}
+
+impl RuntimeError {
+    pub(crate) fn new(message: impl Into<String>, span: Span) -> Self {
+        RuntimeError { kind: "RuntimeError".to_string(), message: message.into(), span, trace: Vec::new(), payload: None }
+    }
+
+    pub(crate) fn with_kind(mut self, kind: impl Into<String>) -> Self {
+        self.kind = kind.into();
+        self
+    }
+
+    fn with_payload(mut self, payload: Value) -> Self {
+        self.payload = Some(Box::new(payload));
+        self
+    }
+
+    fn called_from(mut self, function: &str, span: Span) -> Self {
+        self.trace.push((function.to_string(), span));
+        self
+    }
+
+    // `error.trace`, one "'f' called at line L, column C" string per call.
+    // A run of the same call, from a function calling itself, is one line.
+    fn trace_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut frames = self.trace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let mut count = 1;
+            while frames.next_if_eq(&frame).is_some() {
+                count += 1;
+            }
+            let (function, span) = frame;
+            let mut line = format!("'{}' called at line {}, column {}", function, span.line, span.column);
+            if count > 1 {
+                line.push_str(&format!(" (repeated {} times)", count));
+            }
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+// Calls listed under an uncaught error, a stack overflow would list hundreds
+const MAX_TRACE_NOTES: usize = 5;
+
+impl From<RuntimeError> for Diagnostic {
+    fn from(error: RuntimeError) -> Self {
+        let mut diagnostic = Diagnostic::error("E0200", &error.message, error.span).with_label(&error.kind);
+        let lines = error.trace_lines();
+        for line in lines.iter().take(MAX_TRACE_NOTES) {
+            diagnostic = diagnostic.with_note(format!("in {}", line));
+        }
+        if lines.len() > MAX_TRACE_NOTES {
+            diagnostic = diagnostic.with_note(format!("... and {} more", lines.len() - MAX_TRACE_NOTES));
+        }
+        diagnostic
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+// How a statement finished. `break` and `continue` travel up through the
+// enclosing blocks until the loop that handles them.
+#[derive(Debug)]
+enum ControlFlow {
+    Normal,
+    Break,
+    Continue,
+    Return(Option<Value>), // `return` with its value, if it had one
+}
+
 // Define the Interpreter struct
 pub struct Interpreter {
-    variables: HashMap<String, (Value, bool)>, // Store variables
+    environment: Rc<RefCell<Environment>>,    // Innermost scope of the code being run, the globals at the top level
+    functions: HashMap<String, Rc<Function>>, // Built-in and user functions, apart from variables
+    call_depth: usize,                        // Number of function calls currently running
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        Interpreter {
-            variables: HashMap::new(),
-        }
+        let mut interpreter = Interpreter {
+            environment: Environment::new(None),
+            functions: HashMap::new(),
+            call_depth: 0,
+        };
+        interpreter.add_builtin(Function::builtin("funcs", &[], Interpreter::builtin_funcs));
+        interpreter.add_builtin(Function::builtin("error", &["kind", "message"], Interpreter::builtin_error));
+        strings::add_builtins(&mut interpreter);
+        interpreter
+    }
+
+    pub(crate) fn add_builtin(&mut self, function: Function) {
+        self.functions.insert(function.name.clone(), Rc::new(function));
     }
 
-    pub fn interpret(&mut self, ast: Vec<ASTNode>) -> Result<(), String> {
+    pub fn interpret(&mut self, ast: Vec<ASTNode>) -> Result<(), RuntimeError> {
         for node in ast {
-            match node {
-                ASTNode::ShowStatement { value } => {
-                    self.execute_show(value);
-                }
-                ASTNode::VariableDeclaration { name, is_constant, value } => {
-                    self.handle_variable_declaration(name, is_constant, value)?;
-                }
-                ASTNode::DelVar { name } => {
-                    self.handle_delvar_statement(name)?;
-                }
-                ASTNode::IfStatement { condition, consequent, alternative } => {
-                    self.execute_if_statement(*condition, *consequent, alternative)?;
-                }
-                ASTNode::Block { statements } => {
-                    for stmt in statements {
-                        self.interpret(vec![stmt])?;
-                    }
-                }                
-                ASTNode::Uppercase { expr } => {}
-                ASTNode::Lowercase { expr } => {}
-                ASTNode::ValueBool { value } => {}
-                ASTNode::Variable { name } => {}
-                ASTNode::Value { value } => {}
-                ASTNode::ValueNum { value } => {}
-                ASTNode::BinaryOperation { left, operator, right } => {
-                    let result = self.evaluate_binary_operation(*left, operator, *right)?;
-                    println!("{}", result); // Print the result of the binary operation
-                }
-                ASTNode::Identifier { name } => {
-                    // Handle the identifier, such as printing or evaluating the variable
-                    if let Some((var_value, _)) = self.variables.get(&name) {
-                        println!("{}", var_value); // Print the value of the variable
-                    } else {
-                        println!("Error: Variable '{}' not found", name); // Error if variable is not found
-                    }
-                }
-            }
+            // The parser rejects `break`, `continue` and `return` outside of
+            // loops and functions, so nothing but `Normal` reaches the top level
+            self.execute_statement(node)?;
         }
         Ok(()) // Return Ok if no errors occur
     }
 
+    fn execute_statement(&mut self, node: ASTNode) -> Result<ControlFlow, RuntimeError> {
+        match node {
+            ASTNode::ShowStatement { value, .. } => {
+                self.execute_show(*value)?;
+            }
+            ASTNode::VariableDeclaration { name, is_constant, value, span, .. } => {
+                self.handle_variable_declaration(name, is_constant, *value, span)?;
+            }
+            ASTNode::Assignment { name, value, span } => {
+                self.handle_assignment(name, *value, span)?;
+            }
+            ASTNode::DelVar { name, span } => {
+                self.handle_delvar_statement(name, span)?;
+            }
+            ASTNode::DelFunc { name, span } => {
+                self.handle_delfunc_statement(name, span)?;
+            }
+            ASTNode::IfStatement { condition, consequent, alternative, .. } => {
+                return self.execute_if_statement(*condition, *consequent, alternative);
+            }
+            ASTNode::WhileLoop { condition, body, .. } => {
+                return self.execute_while_loop(*condition, *body);
+            }
+            ASTNode::CountedLoop { variable, start, end, step, body, span } => {
+                return self.execute_counted_loop(variable, *start, *end, step, *body, span);
+            }
+            ASTNode::ForInLoop { variable, iterable, body, span } => {
+                return self.execute_for_in_loop(variable, *iterable, *body, span);
+            }
+            ASTNode::FunctionDeclaration { name, params, body, span, .. } => {
+                self.handle_function_declaration(name, params, *body, span)?;
+            }
+            ASTNode::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => Some(self.evaluate_value(*value)?),
+                    None => None,
+                };
+                return Ok(ControlFlow::Return(value));
+            }
+            ASTNode::Call { callee, arguments, optional, span } => {
+                self.evaluate_call(*callee, arguments, optional, span)?; // The return value is thrown away
+            }
+            ASTNode::TryStatement { body, error_name, handler, finally, span } => {
+                return self.execute_try_statement(*body, error_name, handler, finally, span);
+            }
+            ASTNode::Throw { value, span } => {
+                return Err(match self.evaluate_value(*value)? {
+                    // Throwing a caught error again keeps where it first went wrong
+                    Value::Error(error) => (*error).clone(),
+                    other => RuntimeError::new(other.to_string(), span).with_kind("Error").with_payload(other),
+                });
+            }
+            ASTNode::FunctionExpression { .. } => {}
+            ASTNode::ArrayLiteral { .. } => {}
+            ASTNode::ArraySize { .. } => {}
+            ASTNode::ObjectLiteral { .. } => {}
+            ASTNode::Void { .. } => {}
+            ASTNode::IsVoid { .. } => {}
+            ASTNode::ToJson { .. } => {}
+            ASTNode::ParseJson { .. } => {}
+            ASTNode::DeleteKey { object, key, .. } => {
+                self.handle_delete_key(*object, *key)?;
+            }
+            ASTNode::IndexAssignment { object, index, value, .. } => {
+                self.handle_index_assignment(*object, *index, *value)?;
+            }
+            ASTNode::ArrayAdd { array, value, .. } => {
+                let array = self.evaluate_array(*array)?;
+                let value = self.evaluate_value(*value)?;
+                array.borrow_mut().push(value);
+            }
+            ASTNode::ArrayDelete { array, value, span } => {
+                self.handle_array_delete(*array, *value, span)?;
+            }
+            ASTNode::Break { .. } => return Ok(ControlFlow::Break),
+            ASTNode::Continue { .. } => return Ok(ControlFlow::Continue),
+            block @ ASTNode::Block { .. } => {
+                return self.execute_block(block);
+            }
+            ASTNode::Uppercase { .. } => {}
+            ASTNode::Lowercase { .. } => {}
+            ASTNode::ValueBool { .. } => {}
+            ASTNode::Variable { .. } => {}
+            ASTNode::Value { .. } => {}
+            ASTNode::ValueInt { .. } => {}
+            ASTNode::ValueBigInt { .. } => {}
+            ASTNode::ValueFloat { .. } => {}
+            ASTNode::Template { .. } => {}
+            ASTNode::Range { .. } => {}
+            expression @ (ASTNode::BinaryOperation { .. }
+            | ASTNode::UnaryOperation { .. }
+            | ASTNode::Identifier { .. }
+            | ASTNode::Index { .. }) => {
+                self.evaluate_value(expression)?; // The value is thrown away
+            }
+            ASTNode::Error { span } => {
+                return Err(RuntimeError::new("Cannot run code that failed to parse", span));
+            }
+        }
+        Ok(ControlFlow::Normal)
+    }
+
     fn execute_if_statement(
         &mut self,
         condition: ASTNode,
         consequent: ASTNode,
         alternative: Option<Box<ASTNode>>,
-    ) -> Result<(), String> {
+    ) -> Result<ControlFlow, RuntimeError> {
+        let condition_span = condition.span();
+
         // Evaluate the condition to a boolean value
-        let condition_value = self.evaluate_value(Box::new(condition))?;
+        let condition_value = self.evaluate_value(condition)?;
         match condition_value {
             Value::Boolean(true) => {
                 // Execute the consequent block if condition is true
@@ -89,195 +445,1244 @@ impl Interpreter {
             }
             Value::Boolean(false) => {
                 // Execute the alternative block if it exists and condition is false
-                if let Some(alt) = alternative {
-                    self.execute_block(*alt)
-                } else {
-                    Ok(()) // No alternative block, so do nothing
+                match alternative {
+                    Some(alt) => self.execute_statement(*alt), // A block, or the next `if` of an `else if` chain
+                    None => Ok(ControlFlow::Normal), // No alternative block, so do nothing
                 }
             }
-            _ => Err("Error: Condition expression must evaluate to a boolean".to_string()),
+            _ => Err(RuntimeError::new("Condition expression must evaluate to a boolean", condition_span).with_kind("TypeError")),
         }
     }
 
-    fn execute_block(&mut self, block: ASTNode) -> Result<(), String> {
-        match block {
-            ASTNode::Block { statements } => {
-                for stmt in statements {
-                    self.interpret(vec![stmt.clone()])?; // Interpret each statement in the block
-                }
-                Ok(())
+    // Loops finish with `Normal`, unless a `return` inside them ends the function
+    fn execute_while_loop(&mut self, condition: ASTNode, body: ASTNode) -> Result<ControlFlow, RuntimeError> {
+        loop {
+            let condition_span = condition.span();
+            match self.evaluate_value(condition.clone())? {
+                Value::Boolean(true) => {}
+                Value::Boolean(false) => return Ok(ControlFlow::Normal),
+                _ => return Err(RuntimeError::new("Condition expression must evaluate to a boolean", condition_span).with_kind("TypeError")),
+            }
+
+            match self.execute_block(body.clone())? {
+                ControlFlow::Break => return Ok(ControlFlow::Normal),
+                ControlFlow::Continue | ControlFlow::Normal => {}
+                flow @ ControlFlow::Return(_) => return Ok(flow),
             }
-            _ => Err("Expected a block of statements".to_string()),
         }
     }
 
-    fn handle_delvar_statement(&mut self, name: String) -> Result<(), String> {
-        // Check if the name is "all" to delete all mutable variables
-        if name == "all" {
-            let mut to_remove = Vec::new();
-            
-            // Collect names of mutable (non-constant) variables to remove
-            for (var_name, (_, is_constant)) in &self.variables {
-                if !*is_constant {
-                    to_remove.push(var_name.clone());
+    fn execute_counted_loop(
+        &mut self,
+        variable: String,
+        start: ASTNode,
+        end: ASTNode,
+        step: Option<Box<ASTNode>>,
+        body: ASTNode,
+        span: Span,
+    ) -> Result<ControlFlow, RuntimeError> {
+        let start = self.evaluate_number(start, "Loop start")?;
+        let end = self.evaluate_number(end, "Loop end")?;
+        let step = match step {
+            Some(step) => {
+                let step_span = step.span();
+                let step = self.evaluate_number(*step, "Loop step")?;
+                if numbers::equals(&step, &Value::Int(0)) {
+                    return Err(RuntimeError::new("Loop step cannot be zero", step_span).with_kind("ValueError"));
+                }
+                step
+            }
+            None => Value::Int(1),
+        };
+
+        // `end` is included, a negative step counts down to it
+        let counting_up = numbers::compare(&step, &Value::Int(0)) == Some(Ordering::Greater);
+        let values = std::iter::successors(Some(start), |i| numbers::binary("+", i, &step, span).ok())
+            .take_while(|i| match numbers::compare(i, &end) {
+                Some(Ordering::Equal) => true,
+                Some(Ordering::Less) => counting_up,
+                Some(Ordering::Greater) => !counting_up,
+                None => false,
+            });
+        self.run_loop(variable, values, &body, span)
+    }
+
+    fn execute_for_in_loop(&mut self, variable: String, iterable: ASTNode, body: ASTNode, span: Span) -> Result<ControlFlow, RuntimeError> {
+        match iterable {
+            ASTNode::Range { start, end, .. } => {
+                let start = self.evaluate_number(*start, "Range start")?;
+                let end = self.evaluate_number(*end, "Range end")?;
+                let values = std::iter::successors(Some(start), |i| numbers::binary("+", i, &Value::Int(1), span).ok())
+                    .take_while(|i| numbers::compare(i, &end) == Some(Ordering::Less));
+                self.run_loop(variable, values, &body, span)
+            }
+            iterable => {
+                let iterable_span = iterable.span();
+                match self.evaluate_value(iterable)? {
+                    Value::String(text) => {
+                        // One character at a time as a reader sees them, like the string library
+                        let values: Vec<Value> = text.graphemes(true).map(|c| Value::String(c.to_string())).collect();
+                        self.run_loop(variable, values, &body, span)
+                    }
+                    Value::Array(elements) => {
+                        // Loop over a copy, so the body can change the array safely
+                        let values = elements.borrow().clone();
+                        self.run_loop(variable, values, &body, span)
+                    }
+                    Value::Object(entries) => {
+                        let keys: Vec<Value> = entries.borrow().keys().map(|key| Value::String(key.clone())).collect();
+                        self.run_loop(variable, keys, &body, span)
+                    }
+                    other => Err(RuntimeError::new(format!("Cannot iterate over {}", other.describe()), iterable_span).with_kind("TypeError")),
                 }
             }
-    
-            // Remove each collected mutable variable
-            for var_name in to_remove {
-                self.variables.remove(&var_name);
+        }
+    }
+
+    // Run `body` once for every value. Each pass gets a fresh scope holding
+    // `variable`, so the loop variable is gone once the loop ends.
+    fn run_loop(
+        &mut self,
+        variable: String,
+        values: impl IntoIterator<Item = Value>,
+        body: &ASTNode,
+        span: Span,
+    ) -> Result<ControlFlow, RuntimeError> {
+        for value in values {
+            let scope = Environment::new(Some(Rc::clone(&self.environment)));
+            scope.borrow_mut().define(variable.clone(), Binding { value, is_constant: false, line: span.line });
+
+            match self.in_scope(scope, |interpreter| interpreter.execute_block(body.clone()))? {
+                ControlFlow::Break => break,
+                ControlFlow::Continue | ControlFlow::Normal => {}
+                flow @ ControlFlow::Return(_) => return Ok(flow),
             }
-    
+        }
+        Ok(ControlFlow::Normal)
+    }
+
+    fn handle_function_declaration(&mut self, name: String, params: Vec<String>, body: ASTNode, span: Span) -> Result<(), RuntimeError> {
+        if self.functions.get(&name).is_some_and(|function| function.is_builtin()) {
+            return Err(RuntimeError::new(format!("Built-in function '{}' cannot be redeclared", name), span));
+        }
+        let env = Rc::clone(&self.environment);
+        let function = Function { name: name.clone(), params, body: FunctionBody::User { body, env } };
+        self.functions.insert(name, Rc::new(function));
+        Ok(())
+    }
+
+    fn handle_delfunc_statement(&mut self, name: String, span: Span) -> Result<(), RuntimeError> {
+        // `delfunc all` deletes every user function, built-ins stay
+        if name == "all" {
+            self.functions.retain(|_, function| function.is_builtin());
             return Ok(());
         }
-    
-        // If name is not "all", proceed with single variable deletion
-        if let Some((_, is_constant)) = self.variables.get(&name) {
-            if *is_constant {
-                return Err(format!("Error: Cannot delete constant '{}'", name));
+
+        match self.functions.get(&name) {
+            Some(function) if function.is_builtin() => {
+                Err(RuntimeError::new(format!("Cannot delete built-in function '{}'", name), span))
             }
-            self.variables.remove(&name);
-            Ok(())
-        } else {
-            Err(format!("Error: Variable '{}' not found", name))
+            Some(_) => {
+                self.functions.remove(&name);
+                Ok(())
+            }
+            None => Err(RuntimeError::new(format!("Function '{}' not found", name), span).with_kind("NameError")),
         }
     }
-    
 
-    // Execute a show statement
-    fn execute_show(&mut self, value: Box<ASTNode>) {
-        match *value {
-            ASTNode::Value { value } => {
-                // Print the value directly if it's a simple value
-                println!("{}", value);
-            }
-            ASTNode::ValueNum { value } => {
-                // Print the value directly if it's a simple value
-                println!("{}", value);
-            }
-            ASTNode::ValueBool { value } => {
-                // Print the value directly if it's a simple value
-                println!("{}", value);
-            }
-            ASTNode::Variable { name } => {
-                // Look up the variable in the context and print its value
-                if let Some((var_value, _)) = self.variables.get(&name) {
-                    println!("{}", var_value);
-                } else {
-                    println!("Error: Variable '{}' not found", name);
-                }
+    // `error(kind, message)`: an error value for `throw`, `throw error("ValueError", "too big")`
+    fn builtin_error(&mut self, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+        match (&arguments[0], &arguments[1]) {
+            (Value::String(kind), Value::String(message)) => {
+                Ok(Value::Error(Rc::new(RuntimeError::new(message.clone(), span).with_kind(kind.clone()))))
             }
-            ASTNode::BinaryOperation { left, operator, right } => {
-                // If it's a binary operation, evaluate it first
-                match self.evaluate_binary_operation(*left, operator, *right) {
-                    Ok(result) => println!("{}", result),
-                    Err(e) => println!("Error: {}", e),
+            (kind, message) => Err(RuntimeError::new(
+                format!("error expects a kind and a message that are strings, found {} and {}", kind.describe(), message.describe()),
+                span,
+            ).with_kind("TypeError")),
+        }
+    }
+
+    // `funcs()`: an array of every function that can be called, as `name/arity` sorted by name
+    fn builtin_funcs(&mut self, _arguments: Vec<Value>, _span: Span) -> Result<Value, RuntimeError> {
+        let mut names: Vec<String> = self.functions.values()
+            .map(|function| format!("{}/{}", function.name, function.params.len()))
+            .collect();
+        names.sort();
+        Ok(Value::Array(Rc::new(RefCell::new(names.into_iter().map(Value::String).collect()))))
+    }
+
+    // Evaluate `callee(arguments)`. A plain name calls the variable with that
+    // name if it holds a function, otherwise the function table is used.
+    fn evaluate_call(&mut self, callee: ASTNode, arguments: Vec<ASTNode>, optional: bool, span: Span) -> Result<Option<Value>, RuntimeError> {
+        let function = match callee {
+            ASTNode::Variable { ref name, .. } => {
+                let variable = self.environment.borrow().get(name);
+                match (variable, self.functions.get(name)) {
+                    (Some(value @ (Value::Function(_) | Value::Closure(_))), _) => value,
+                    (_, Some(function)) => Value::Function(Rc::clone(function)),
+                    (Some(other), None) => {
+                        return Err(RuntimeError::new(format!("'{}' is not a function, it holds {}", name, other.describe()), span).with_kind("TypeError"));
+                    }
+                    (None, None) => return Err(RuntimeError::new(format!("Function '{}' not found", name), span).with_kind("NameError")),
                 }
             }
-            _ => {}
+            callee => match self.evaluate_value(callee)? {
+                Value::Null if optional => return Ok(None), // `a?.f()` with no `f`, the arguments aren't evaluated
+                function => function,
+            },
+        };
+
+        // Evaluate every argument before any parameter is bound
+        let mut values = Vec::new();
+        for argument in arguments {
+            values.push(self.evaluate_value(argument)?);
         }
+
+        self.call_value(function, values, span)
+    }
+
+    // Call a function and hand back what it returned, `None` if it finished without `return value`
+    fn call_value(&mut self, function: Value, arguments: Vec<Value>, span: Span) -> Result<Option<Value>, RuntimeError> {
+        let function = match function {
+            Value::Function(function) | Value::Closure(function) => function,
+            other => return Err(RuntimeError::new(format!("{} is not a function", other.describe()), span).with_kind("TypeError")),
+        };
+        let name = &function.name;
+
+        if arguments.len() != function.params.len() {
+            return Err(RuntimeError::new(
+                format!("Function '{}' expects {} argument(s) but got {}", name, function.params.len(), arguments.len()),
+                span,
+            ).with_kind("TypeError"));
+        }
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::new(
+                format!("stack overflow: more than {} nested function calls in '{}'", MAX_CALL_DEPTH, name),
+                span,
+            ).with_kind("StackOverflowError"));
+        }
+
+        let (body, env) = match function.body {
+            FunctionBody::User { ref body, ref env } => (body.clone(), Rc::clone(env)),
+            FunctionBody::Builtin(run) => return run(self, arguments, span).map(Some),
+        };
+
+        // The parameters live in a scope of their own on top of the scope the
+        // function was created in, the caller's local variables can't be seen
+        let scope = Environment::new(Some(env));
+        for (param, value) in function.params.iter().zip(arguments) {
+            scope.borrow_mut().define(param.clone(), Binding { value, is_constant: false, line: span.line });
+        }
+
+        self.call_depth += 1;
+        let result = self.in_scope(scope, |interpreter| interpreter.execute_block(body));
+        self.call_depth -= 1;
+
+        match result.map_err(|error| error.called_from(name, span))? {
+            ControlFlow::Return(value) => Ok(value),
+            _ => Ok(None),
+        }
+    }
+
+    // Run `body`, and `handler` if it fails with the error in `error_name`.
+    // `finally` runs last whatever happened, also when the body or handler
+    // fails, returns, breaks or continues. Only a `finally` that does one of
+    // those itself replaces the outcome.
+    fn execute_try_statement(
+        &mut self,
+        body: ASTNode,
+        error_name: Option<String>,
+        handler: Option<Box<ASTNode>>,
+        finally: Option<Box<ASTNode>>,
+        span: Span,
+    ) -> Result<ControlFlow, RuntimeError> {
+        let mut result = self.execute_block(body);
+
+        if let (Err(error), Some(handler)) = (&result, handler) {
+            let scope = Environment::new(Some(Rc::clone(&self.environment)));
+            if let Some(name) = error_name {
+                let value = match error.payload {
+                    Some(ref payload) => (**payload).clone(),
+                    None => Value::Error(Rc::new(error.clone())),
+                };
+                scope.borrow_mut().define(name, Binding { value, is_constant: false, line: span.line });
+            }
+            result = self.in_scope(scope, |interpreter| interpreter.execute_block(*handler));
+        }
+
+        if let Some(finally) = finally {
+            match self.execute_block(*finally)? {
+                ControlFlow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        result
+    }
+
+    // Run the statements of a block in a new scope, stopping early when one
+    // of them breaks, continues or returns
+    fn execute_block(&mut self, block: ASTNode) -> Result<ControlFlow, RuntimeError> {
+        match block {
+            ASTNode::Block { statements, .. } => {
+                let scope = Environment::new(Some(Rc::clone(&self.environment)));
+                self.in_scope(scope, |interpreter| {
+                    for stmt in statements {
+                        let flow = interpreter.execute_statement(stmt)?;
+                        if !matches!(flow, ControlFlow::Normal) {
+                            return Ok(flow);
+                        }
+                    }
+                    Ok(ControlFlow::Normal)
+                })
+            }
+            other => Err(RuntimeError::new("Expected a block of statements", other.span())),
+        }
+    }
+
+    // Run `f` with `scope` as the innermost scope, going back to the current
+    // one afterwards even when `f` fails
+    fn in_scope<T>(
+        &mut self,
+        scope: Rc<RefCell<Environment>>,
+        f: impl FnOnce(&mut Self) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        let previous = std::mem::replace(&mut self.environment, scope);
+        let result = f(self);
+        self.environment = previous;
+        result
+    }
+
+    fn handle_delvar_statement(&mut self, name: String, span: Span) -> Result<(), RuntimeError> {
+        // Check if the name is "all" to delete every mutable variable in reach
+        if name == "all" {
+            self.environment.borrow_mut().remove_all_mutable();
+            return Ok(());
+        }
+
+        // If name is not "all", delete the nearest variable with that name
+        match self.environment.borrow_mut().remove(&name) {
+            Ok(()) => Ok(()),
+            Err(BindingError::Constant { line }) => Err(RuntimeError::new(
+                format!("Cannot delete constant '{}' declared at line {}", name, line),
+                span,
+            )),
+            Err(BindingError::NotFound) => Err(RuntimeError::new(format!("Variable '{}' not found", name), span).with_kind("NameError")),
+        }
+    }
+
+
+    // Execute a show statement
+    fn execute_show(&mut self, value: ASTNode) -> Result<(), RuntimeError> {
+        let result = self.evaluate_value(value)?;
+        println!("{}", result);
+        Ok(())
     }
-    
 
-    fn handle_variable_declaration(&mut self, name: String, is_constant: bool, value_node: Box<ASTNode>) -> Result<(), String> {
+
+    fn handle_variable_declaration(&mut self, name: String, is_constant: bool, value_node: ASTNode, span: Span) -> Result<(), RuntimeError> {
         let value = self.evaluate_value(value_node)?;
 
-        if let Some((_, existing_is_constant)) = self.variables.get(&name) {
-            if *existing_is_constant {
-                return Err(format!("Error: Constant '{}' cannot be reassigned", name));
+        // Declaring a name again in the same scope follows the old rules, in
+        // an inner scope it shadows the outer variable instead
+        let mut environment = self.environment.borrow_mut();
+        if let Some(existing) = environment.local(&name) {
+            if existing.is_constant {
+                return Err(RuntimeError::new(
+                    format!("Constant '{}' declared at line {} cannot be reassigned", name, existing.line),
+                    span,
+                ));
             } else if is_constant {
-                return Err(format!("Error: Mutable '{}' cannot be reassigned as constant", name));
-            } else {
-                self.variables.insert(name, (value, is_constant));
-                return Ok(());
+                return Err(RuntimeError::new(format!("Mutable '{}' cannot be reassigned as constant", name), span));
             }
         }
 
-        self.variables.insert(name, (value, is_constant));
+        environment.define(name, Binding { value, is_constant, line: span.line });
         Ok(())
     }
 
-    fn evaluate_value(&mut self, value_node: Box<ASTNode>) -> Result<Value, String> {
-        match *value_node {
-            ASTNode::ValueNum { value } => Ok(Value::Number(value)),
-            ASTNode::Value { value } => Ok(Value::String(value)),
-            ASTNode::Identifier { name } => {
-                if let Some((var_value, _)) = self.variables.get(&name) {
-                    Ok(var_value.clone())
+    // `x = value` changes the nearest `x`, or creates it in the current scope if there is none
+    fn handle_assignment(&mut self, name: String, value_node: ASTNode, span: Span) -> Result<(), RuntimeError> {
+        let value = self.evaluate_value(value_node)?;
+
+        let mut environment = self.environment.borrow_mut();
+        if environment.get(&name).is_none() {
+            // `x = 1` with no `x` in sight declares a global, so it is still
+            // there once the block or function it ran in has finished
+            environment.define_global(name, Binding { value, is_constant: false, line: span.line });
+            return Ok(());
+        }
+
+        match environment.assign(&name, value) {
+            Ok(()) => Ok(()),
+            Err(BindingError::Constant { line }) => Err(RuntimeError::new(
+                format!("Cannot assign to constant '{}' declared at line {}", name, line),
+                span,
+            )),
+            Err(BindingError::NotFound) => Err(RuntimeError::new(format!("Variable '{}' not found", name), span).with_kind("NameError")),
+        }
+    }
+
+    fn evaluate_value(&mut self, value_node: ASTNode) -> Result<Value, RuntimeError> {
+        match value_node {
+            ASTNode::ValueInt { value, .. } => Ok(Value::Int(value)),
+            ASTNode::ValueBigInt { value, .. } => Ok(Value::BigInt(value)),
+            ASTNode::ValueFloat { value, .. } => Ok(Value::Float(value)),
+            ASTNode::Value { value, .. } => Ok(Value::String(value)),
+            ASTNode::Identifier { name, span } | ASTNode::Variable { name, span } => {
+                if let Some(var_value) = self.environment.borrow().get(&name) {
+                    Ok(var_value)
+                } else if let Some(function) = self.functions.get(&name) {
+                    Ok(Value::Function(Rc::clone(function))) // A function used by name, like `show addVal`
                 } else {
-                    Err(format!("Error: Variable '{}' not found", name))
+                    Err(RuntimeError::new(format!("Variable '{}' not found", name), span).with_kind("NameError"))
+                }
+            }
+            ASTNode::ValueBool { value, .. } => Ok(Value::Boolean(value)), // Handling for boolean literals
+            ASTNode::Template { parts, .. } => {
+                // Stringify every part with `Display` and glue them together
+                let mut text = String::new();
+                for part in parts {
+                    text.push_str(&self.evaluate_value(part)?.to_string());
+                }
+                Ok(Value::String(text))
+            }
+            ASTNode::BinaryOperation { left, operator, right, span } => {
+                self.evaluate_binary_operation(*left, operator, *right, span)
+            }
+            ASTNode::UnaryOperation { operator, operand, span } => {
+                self.evaluate_unary_operation(operator, *operand, span)
+            }
+            // A function that finishes without `return value` gives void
+            ASTNode::Call { callee, arguments, optional, span } => {
+                Ok(self.evaluate_call(*callee, arguments, optional, span)?.unwrap_or(Value::Null))
+            }
+            ASTNode::FunctionExpression { name, params, body, .. } => {
+                let function = Function {
+                    name: name.unwrap_or_else(|| "anonymous".to_string()),
+                    params,
+                    body: FunctionBody::User { body: *body, env: Rc::clone(&self.environment) },
+                };
+                Ok(Value::Closure(Rc::new(function)))
+            }
+            ASTNode::ArrayLiteral { elements, .. } => {
+                let mut values = Vec::new();
+                for element in elements {
+                    values.push(self.evaluate_value(element)?);
+                }
+                Ok(Value::Array(Rc::new(RefCell::new(values))))
+            }
+            ASTNode::ObjectLiteral { entries, .. } => {
+                let mut object = IndexMap::new();
+                for (key, value) in entries {
+                    let value = self.evaluate_value(value)?;
+                    object.insert(key, value);
+                }
+                Ok(Value::Object(Rc::new(RefCell::new(object))))
+            }
+            // Reading a missing key gives void, so does anything void after `?.`
+            ASTNode::Index { object, index, optional, .. } => {
+                let object_span = object.span();
+                let object = self.evaluate_value(*object)?;
+                if optional && object == Value::Null {
+                    return Ok(Value::Null);
+                }
+                let index_span = index.span();
+                let index = self.evaluate_value(*index)?;
+                match object {
+                    Value::Array(elements) => {
+                        let elements = elements.borrow();
+                        match array_position(&index, elements.len(), index_span) {
+                            Ok(position) => Ok(elements[position].clone()),
+                            Err(error) if optional && error.kind == "IndexError" => Ok(Value::Null),
+                            Err(error) => Err(error),
+                        }
+                    }
+                    Value::Object(entries) => {
+                        let key = object_key(index, index_span)?;
+                        Ok(entries.borrow().get(&key).cloned().unwrap_or(Value::Null))
+                    }
+                    Value::Error(error) => Ok(error_field(&error, &object_key(index, index_span)?)),
+                    other => Err(RuntimeError::new(format!("Cannot index into {}", other.describe()), object_span).with_kind("TypeError")),
                 }
             }
-            ASTNode::ValueBool { value } => Ok(Value::Boolean(value)), // Handling for boolean literals
-            ASTNode::BinaryOperation { left, operator, right } => {
-                self.evaluate_binary_operation(*left, operator, *right)
+            ASTNode::ArraySize { array, .. } => {
+                let elements = self.evaluate_array(*array)?;
+                let size = elements.borrow().len();
+                Ok(Value::Int(size as i64))
+            }
+            ASTNode::Uppercase { expr, span } => strings::change_case(self.evaluate_value(*expr)?, true, span),
+            ASTNode::Lowercase { expr, span } => strings::change_case(self.evaluate_value(*expr)?, false, span),
+            ASTNode::Void { .. } => Ok(Value::Null),
+            ASTNode::IsVoid { value, .. } => Ok(Value::Boolean(self.evaluate_value(*value)? == Value::Null)),
+            ASTNode::ToJson { value, pretty, span } => {
+                let json = value_to_json(&self.evaluate_value(*value)?, span, &mut Vec::new())?;
+                let text = if pretty { serde_json::to_string_pretty(&json) } else { serde_json::to_string(&json) };
+                Ok(Value::String(text.expect("serializing a serde_json::Value can't fail")))
+            }
+            ASTNode::ParseJson { text, .. } => {
+                let text_span = text.span();
+                let text = match self.evaluate_value(*text)? {
+                    Value::String(text) => text,
+                    other => return Err(RuntimeError::new(format!("parjson expects a string, found {}", other.describe()), text_span).with_kind("TypeError")),
+                };
+                match serde_json::from_str(&text) {
+                    Ok(json) => match json_number_out_of_range(&text) {
+                        Some((line, column, number)) => Err(RuntimeError::new(
+                            format!("Invalid JSON at line {}, column {}: number {} is out of range", line, column, number),
+                            text_span,
+                        ).with_kind("ValueError")),
+                        None => Ok(json_to_value(json)),
+                    },
+                    Err(error) => Err(RuntimeError::new(
+                        format!("Invalid JSON at line {}, column {}: {}", error.line(), error.column(), json_error_reason(&error)),
+                        text_span,
+                    ).with_kind("ValueError")),
+                }
             }
-            _ => Err("Invalid value node".to_string()),
+            ASTNode::Range { span, .. } => Err(RuntimeError::new("A range can only be used in a for loop", span)),
+            other => Err(RuntimeError::new("Invalid value node", other.span())),
+        }
+    }
+
+    // Evaluate a node that has to produce an array
+    fn evaluate_array(&mut self, node: ASTNode) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+        let span = node.span();
+        match self.evaluate_value(node)? {
+            Value::Array(elements) => Ok(elements),
+            other => Err(RuntimeError::new(format!("Expected an array, found {}", other.describe()), span).with_kind("TypeError")),
+        }
+    }
+
+    // `array[i] = value` replaces an element, `object.key = value` also adds new keys
+    fn handle_index_assignment(&mut self, object: ASTNode, index: ASTNode, value: ASTNode) -> Result<(), RuntimeError> {
+        let object_span = object.span();
+        let object = self.evaluate_value(object)?;
+        let index_span = index.span();
+        let index = self.evaluate_value(index)?;
+        let value = self.evaluate_value(value)?;
+
+        match object {
+            Value::Array(elements) => {
+                let mut elements = elements.borrow_mut();
+                let position = array_position(&index, elements.len(), index_span)?;
+                elements[position] = value;
+            }
+            Value::Object(entries) => {
+                let key = object_key(index, index_span)?;
+                entries.borrow_mut().insert(key, value);
+            }
+            other => return Err(RuntimeError::new(format!("Cannot index into {}", other.describe()), object_span).with_kind("TypeError")),
+        }
+        Ok(())
+    }
+
+    fn handle_delete_key(&mut self, object: ASTNode, key: ASTNode) -> Result<(), RuntimeError> {
+        let object_span = object.span();
+        let key_span = key.span();
+        let entries = match self.evaluate_value(object)? {
+            Value::Object(entries) => entries,
+            other => return Err(RuntimeError::new(format!("Expected an object, found {}", other.describe()), object_span).with_kind("TypeError")),
+        };
+        let key = object_key(self.evaluate_value(key)?, key_span)?;
+
+        // `shift_remove` keeps the other keys in order
+        if entries.borrow_mut().shift_remove(&key).is_none() {
+            return Err(RuntimeError::new(format!("Key '{}' not found in the object", key), key_span).with_kind("KeyError"));
+        }
+        Ok(())
+    }
+
+    // `arrdel array 2` removes the element at index 2, `arrdel array "x"` the first "x"
+    fn handle_array_delete(&mut self, array: ASTNode, value: ASTNode, span: Span) -> Result<(), RuntimeError> {
+        let elements = self.evaluate_array(array)?;
+        let value_span = value.span();
+        let value = self.evaluate_value(value)?;
+
+        // Comparing can look inside arrays held by this one, or at the array
+        // itself, so it is only borrowed mutably once the position is known
+        let position = {
+            let elements = elements.borrow();
+            match value {
+                Value::Int(_) | Value::BigInt(_) | Value::Float(_) => array_position(&value, elements.len(), value_span)?,
+                value => match elements.iter().position(|element| *element == value) {
+                    Some(position) => position,
+                    None => {
+                        return Err(RuntimeError::new(format!("{} not found in the array", value.describe()), span).with_kind("ValueError"));
+                    }
+                },
+            }
+        };
+        elements.borrow_mut().remove(position);
+        Ok(())
+    }
+
+    // Evaluate a node that has to produce a number, `what` names it in the error
+    fn evaluate_number(&mut self, node: ASTNode, what: &str) -> Result<Value, RuntimeError> {
+        let span = node.span();
+        match self.evaluate_value(node)? {
+            number if numbers::is_number(&number) => Ok(number),
+            other => Err(RuntimeError::new(format!("{} must be a number, found {}", what, other.describe()), span).with_kind("TypeError")),
+        }
+    }
+
+    fn evaluate_unary_operation(&mut self, operator: String, operand: ASTNode, span: Span) -> Result<Value, RuntimeError> {
+        match (operator.as_str(), self.evaluate_value(operand)?) {
+            ("!", Value::Boolean(value)) => Ok(Value::Boolean(!value)),
+            ("-", value) if numbers::is_number(&value) => Ok(numbers::negate(value)),
+            (_, value) => Err(RuntimeError::new(
+                format!("Unsupported operand for unary '{}': {}", operator, value.describe()),
+                span,
+            ).with_kind("TypeError")),
+        }
+    }
+
+    // `&&` and `||` only look at the right side when the left doesn't decide the result
+    fn evaluate_logical_operation(&mut self, left_node: ASTNode, operator: &str, right_node: ASTNode, span: Span) -> Result<Value, RuntimeError> {
+        let left = match self.evaluate_value(left_node)? {
+            Value::Boolean(value) => value,
+            other => {
+                return Err(RuntimeError::new(
+                    format!("Left operand of '{}' must be a boolean, found {}", operator, other.describe()),
+                    span,
+                ).with_kind("TypeError"));
+            }
+        };
+
+        if (operator == "&&" && !left) || (operator == "||" && left) {
+            return Ok(Value::Boolean(left));
+        }
+
+        match self.evaluate_value(right_node)? {
+            Value::Boolean(right) => Ok(Value::Boolean(right)),
+            other => Err(RuntimeError::new(
+                format!("Right operand of '{}' must be a boolean, found {}", operator, other.describe()),
+                span,
+            ).with_kind("TypeError")),
+        }
+    }
+
+    // How operands of different types combine:
+    //
+    //   left     op                 right    result
+    //   number   + - * / // % ** ^  number   arithmetic, see `numbers`
+    //   number   < > <= >=          number   numeric comparison
+    //   string   +                  string   the two joined together
+    //   string   +                  number   the number written as `show` would, then joined,
+    //   number   +                  string   so "a" + 1 is "a1" and 1 + "a" is "1a"
+    //   string   < > <= >=          string   lexicographic, by Unicode code point
+    //   any      == !=              any      loosely equal, see `loose_equals`
+    //   any      === !==            any      strictly equal, same type and same value,
+    //                                         so 1 == 1.0 but 1 !== 1.0
+    //
+    // Every other combination is a type mismatch error. Nothing else is
+    // coerced, `true + 1` and `"1" < 2` are errors.
+    fn evaluate_binary_operation(&mut self, left_node: ASTNode, operator: String, right_node: ASTNode, span: Span) -> Result<Value, RuntimeError> {
+        if operator == "&&" || operator == "||" {
+            return self.evaluate_logical_operation(left_node, &operator, right_node, span);
+        }
+        // `a ?? b` is `a` unless it is void, `b` is only evaluated when needed
+        if operator == "??" {
+            return match self.evaluate_value(left_node)? {
+                Value::Null => self.evaluate_value(right_node),
+                left => Ok(left),
+            };
+        }
+
+        let left_value = self.evaluate_value(left_node)?;
+        let right_value = self.evaluate_value(right_node)?;
+
+        match operator.as_str() {
+            "==" => return Ok(Value::Boolean(loose_equals(&left_value, &right_value))),
+            "!=" => return Ok(Value::Boolean(!loose_equals(&left_value, &right_value))),
+            "===" => return Ok(Value::Boolean(strict_equals(&left_value, &right_value))),
+            "!==" => return Ok(Value::Boolean(!strict_equals(&left_value, &right_value))),
+            _ => {}
         }
-    }    
 
-    fn evaluate_binary_operation(&mut self, left_node: ASTNode, operator: String, right_node: ASTNode) -> Result<Value, String> {
-        let left_value = self.evaluate_value(Box::new(left_node))?;
-        let right_value = self.evaluate_value(Box::new(right_node))?;
-    
         match (left_value, right_value) {
             // Handle numeric operations
-            (Value::Number(left), Value::Number(right)) => {
+            (left, right) if numbers::is_number(&left) && numbers::is_number(&right) => {
+                numbers::binary(&operator, &left, &right, span)
+            }
+            (Value::Boolean(_), Value::Boolean(_)) => {
+                Err(RuntimeError::new(format!("Unsupported boolean operator: {}", operator), span).with_kind("TypeError"))
+            }
+            (Value::String(left), Value::String(right)) => {
                 match operator.as_str() {
-                    "+" => Ok(Value::Number(left + right)),
-                    "-" => Ok(Value::Number(left - right)),
-                    "*" => Ok(Value::Number(left * right)),
-                    "/" => {
-                        if right == 0.0 {
-                            Err("Error: Division by zero".to_string())
-                        } else {
-                            Ok(Value::Number(left / right))
-                        }
-                    }
-                    "%" => Ok(Value::Number(left % right)),
-                    "**" => Ok(Value::Number(left.powf(right))),
-                    "==" => Ok(Value::Boolean(left == right)), // Equality check
-                    "!=" => Ok(Value::Boolean(left != right)),
+                    "+" => Ok(Value::String(left + &right)),
                     "<" => Ok(Value::Boolean(left < right)),
                     ">" => Ok(Value::Boolean(left > right)),
                     "<=" => Ok(Value::Boolean(left <= right)),
                     ">=" => Ok(Value::Boolean(left >= right)),
-                    _ => Err(format!("Unsupported operator: {}", operator)),
+                    _ => Err(RuntimeError::new(format!("Unsupported string operator: {}", operator), span).with_kind("TypeError")),
                 }
             }
-            // Handle boolean operations
-            (Value::Boolean(left), Value::Boolean(right)) => {
-                match operator.as_str() {
-                    "&&" => Ok(Value::Boolean(left && right)),
-                    "||" => Ok(Value::Boolean(left || right)),
-                    "==" => Ok(Value::Boolean(left == right)),
-                    "!=" => Ok(Value::Boolean(left != right)),
-                    _ => Err(format!("Unsupported boolean operator: {}", operator)),
-                }
+            (Value::String(left), right) if operator == "+" && numbers::is_number(&right) => {
+                Ok(Value::String(format!("{}{}", left, right)))
+            }
+            (left, Value::String(right)) if operator == "+" && numbers::is_number(&left) => {
+                Ok(Value::String(format!("{}{}", left, right)))
             }
             // Handle mixed types or unsupported operations
-            (left, right) => Err(format!("Type mismatch or unsupported operation between {:?} and {:?}", left, right)),
+            (left, right) => Err(RuntimeError::new(
+                format!("Type mismatch or unsupported operation between {} and {}", left.describe(), right.describe()),
+                span,
+            ).with_kind("TypeError")),
         }
-    }    
-    
+    }
+}
 
-    // Assign a new value to a variable (check if it's mutable)
-    pub fn assign_variable(&mut self, name: String, value_node: Box<ASTNode>) -> Result<(), String> {
-        // Evaluate the value node to get the new value
-        let value = self.evaluate_value(value_node)?;
+// `==`: a number and a string are equal when the string, ignoring spaces
+// around it, is that number written out, so `1 == "1.0"` but `0 != ""`.
+// Any other pair is only equal if it is strictly equal.
+fn loose_equals(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (number, Value::String(text)) | (Value::String(text), number) if numbers::is_number(number) => {
+            numbers::parse(text).is_some_and(|parsed| numbers::equals(&parsed, number))
+        }
+        _ => left == right,
+    }
+}
+
+// `===`: like `==` without any coercion, an integer never equals a float
+fn strict_equals(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Float(_), Value::Int(_) | Value::BigInt(_)) | (Value::Int(_) | Value::BigInt(_), Value::Float(_)) => false,
+        _ => left == right,
+    }
+}
+
+// Turn an index value into a position in an array of `len` elements
+fn array_position(index: &Value, len: usize, span: Span) -> Result<usize, RuntimeError> {
+    match index {
+        Value::Int(n) if *n >= 0 && (*n as usize) < len => Ok(*n as usize),
+        Value::Int(_) | Value::BigInt(_) => Err(RuntimeError::new(
+            format!("Index {} is out of bounds for an array of length {}", index, len),
+            span,
+        ).with_kind("IndexError")),
+        other => Err(RuntimeError::new(format!("Array index must be an integer, found {}", other.describe()), span).with_kind("TypeError")),
+    }
+}
+
+// `error.kind`, `error.message`, `error.line`, `error.column` and `error.trace`
+fn error_field(error: &RuntimeError, key: &str) -> Value {
+    match key {
+        "kind" => Value::String(error.kind.clone()),
+        "message" => Value::String(error.message.clone()),
+        "line" => Value::Int(error.span.line as i64),
+        "column" => Value::Int(error.span.column as i64),
+        "trace" => Value::Array(Rc::new(RefCell::new(error.trace_lines().into_iter().map(Value::String).collect()))),
+        _ => Value::Null,
+    }
+}
+
+fn object_key(key: Value, span: Span) -> Result<String, RuntimeError> {
+    match key {
+        Value::String(key) => Ok(key),
+        other => Err(RuntimeError::new(format!("Object keys must be strings, found {}", other.describe()), span).with_kind("TypeError")),
+    }
+}
 
-        // Check if the variable exists and whether it's constant
-        if let Some((existing_value, is_constant)) = self.variables.get_mut(&name) {
-            if *is_constant {
-                return Err(format!("Error: Cannot reassign constant '{}'", name));
+// `open` holds the arrays and objects being converted around `value`, JSON has no way to
+// write one that holds itself
+fn value_to_json(value: &Value, span: Span, open: &mut Vec<*const ()>) -> Result<serde_json::Value, RuntimeError> {
+    Ok(match value {
+        Value::String(text) => serde_json::Value::from(text.as_str()),
+        Value::Int(n) => serde_json::Value::from(*n),
+        // serde_json keeps the digits of numbers it can't hold itself, so big integers stay exact
+        Value::BigInt(n) => serde_json::Value::Number(n.to_string().parse().expect("an integer is a valid JSON number")),
+        Value::Float(n) => match serde_json::Number::from_f64(*n) {
+            Some(number) => serde_json::Value::Number(number),
+            None => return Err(RuntimeError::new(format!("Cannot convert {} to JSON", n), span).with_kind("ValueError")),
+        },
+        Value::Boolean(b) => serde_json::Value::Bool(*b),
+        Value::Null => serde_json::Value::Null,
+        Value::Array(elements) => {
+            let id = Rc::as_ptr(elements) as *const ();
+            if open.contains(&id) {
+                return Err(RuntimeError::new("Cannot convert an array that contains itself to JSON", span).with_kind("ValueError"));
             }
+            open.push(id);
+            let mut array = Vec::new();
+            for element in elements.borrow().iter() {
+                array.push(value_to_json(element, span, open)?);
+            }
+            open.pop();
+            serde_json::Value::Array(array)
+        }
+        Value::Object(entries) => {
+            let id = Rc::as_ptr(entries) as *const ();
+            if open.contains(&id) {
+                return Err(RuntimeError::new("Cannot convert an object that contains itself to JSON", span).with_kind("ValueError"));
+            }
+            open.push(id);
+            let mut object = serde_json::Map::new();
+            for (key, value) in entries.borrow().iter() {
+                object.insert(key.clone(), value_to_json(value, span, open)?);
+            }
+            open.pop();
+            serde_json::Value::Object(object)
+        }
+        Value::Function(function) | Value::Closure(function) => {
+            return Err(RuntimeError::new(format!("Cannot convert function {} to JSON", function.name), span).with_kind("TypeError"));
+        }
+        Value::Error(error) => {
+            return Err(RuntimeError::new(format!("Cannot convert error {} to JSON", error.kind), span).with_kind("TypeError"));
+        }
+    })
+}
+
+fn json_to_value(json: serde_json::Value) -> Value {
+    match json {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Boolean(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(n) => Value::Int(n),
+            None => numbers::parse(&n.to_string()).unwrap_or_else(|| Value::Float(n.as_f64().unwrap_or(f64::NAN))),
+        },
+        serde_json::Value::String(text) => Value::String(text),
+        serde_json::Value::Array(elements) => {
+            Value::Array(Rc::new(RefCell::new(elements.into_iter().map(json_to_value).collect())))
+        }
+        serde_json::Value::Object(entries) => {
+            let object = entries.into_iter().map(|(key, value)| (key, json_to_value(value))).collect();
+            Value::Object(Rc::new(RefCell::new(object)))
+        }
+    }
+}
 
-            // If it's mutable, update the value
-            *existing_value = value;
+// serde_json keeps numbers of any size, so `1e400` parses. Find the first
+// float in the valid JSON `text` that is too large to hold, with its line
+// and column, so `parjson` can report it instead of turning it into infinity.
+fn json_number_out_of_range(text: &str) -> Option<(usize, usize, &str)> {
+    let (mut line, mut column) = (1, 1);
+    let (mut in_string, mut escaped) = (false, false);
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == '-' || c.is_ascii_digit() {
+            let mut end = start + 1;
+            while let Some(&(i, next)) = chars.peek() {
+                if !(next.is_ascii_digit() || matches!(next, '.' | 'e' | 'E' | '+' | '-')) {
+                    break;
+                }
+                end = i + 1;
+                chars.next();
+            }
+            let number = &text[start..end];
+            let is_float = number.contains(['.', 'e', 'E']);
+            if is_float && !number.parse::<f64>().is_ok_and(f64::is_finite) {
+                return Some((line, column, number));
+            }
+            column += number.chars().count();
+            continue;
+        }
+        if c == '\n' {
+            line += 1;
+            column = 1;
         } else {
-            return Err(format!("Error: Variable '{}' not defined", name));
+            column += 1;
         }
+    }
+    None
+}
 
-        Ok(())
+// serde_json puts " at line L column C" at the end of its messages, the
+// position is reported separately so it is cut off here
+fn json_error_reason(error: &serde_json::Error) -> String {
+    let message = error.to_string();
+    match message.rfind(" at line ") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    // Run a whole program and hand back the interpreter, to look at its variables
+    pub(crate) fn run(source: &str) -> Result<Interpreter, RuntimeError> {
+        let (ast, diagnostics) = Parser::new(Lexer::new(source)).parse();
+        assert!(diagnostics.is_empty(), "{:?} should parse: {:?}", source, diagnostics);
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(ast)?;
+        Ok(interpreter)
+    }
+
+    // Run `f` on a thread with the stack `main` gives the interpreter, test
+    // threads get far less
+    pub(crate) fn on_main_stack<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+        let thread = std::thread::Builder::new().stack_size(crate::STACK_SIZE).spawn(f).unwrap();
+        thread.join().unwrap()
+    }
+
+    // The value of a program's `result` variable
+    pub(crate) fn run_for_result(source: &str) -> Result<Value, RuntimeError> {
+        let interpreter = run(source)?;
+        let result = interpreter.environment.borrow().get("result");
+        Ok(result.expect("the program should set `result`"))
+    }
+
+    #[test]
+    fn template_strings() {
+        let program = "let n = 2\nlet result = `n = ${n + 1}, ${`inner ${n}`}, ${\"a\"} \\${n}`";
+        assert_eq!(run_for_result(program).unwrap().to_string(), "n = 3, inner 2, a ${n}");
+    }
+
+    pub(crate) fn evaluate(expression: &str) -> Result<Value, RuntimeError> {
+        run_for_result(&format!("let result = {}", expression))
+    }
+
+    // The message of the error `expression` fails with, after checking its kind
+    pub(crate) fn error_message(expression: &str, kind: &str) -> String {
+        match evaluate(expression) {
+            Ok(value) => panic!("{} should fail, gave {}", expression, value),
+            Err(error) => {
+                assert_eq!(error.kind, kind, "{}: {}", expression, error.message);
+                error.message
+            }
+        }
+    }
+
+    // `expression` as a statement of its own, the parser doesn't produce those yet
+    fn expression_statement(expression: &str) -> ASTNode {
+        let (mut ast, _) = Parser::new(Lexer::new(&format!("let result = {}", expression))).parse();
+        match ast.remove(0) {
+            ASTNode::VariableDeclaration { value, .. } => *value,
+            other => panic!("expected a declaration, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn comparison_logical_and_unary_operators() {
+        let cases: &[(&str, &str)] = &[
+            ("1 < 2 && 2 <= 2", "true"),
+            ("3 > 4 || 4 >= 4", "true"),
+            ("1 != 2", "true"),
+            ("!true", "false"),
+            ("!(1 > 2)", "true"),
+            ("-(2 + 3)", "-5"),
+            ("-2 ** 2", "-4"),
+            ("1 + 2 * 3 == 7 && !false", "true"),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(evaluate(expression).unwrap().to_string(), *expected, "{}", expression);
+        }
+        // `&&` and `||` stop once the answer is known
+        assert_eq!(evaluate("false && undefined_name").unwrap().to_string(), "false");
+        assert_eq!(evaluate("true || undefined_name").unwrap().to_string(), "true");
+        assert_eq!(error_message("!1", "TypeError"), "Unsupported operand for unary '!': int 1");
+        // As statements their value is thrown away, but errors still count
+        let mut interpreter = run("let x = 1").unwrap();
+        assert!(interpreter.interpret(vec![expression_statement("x + 1")]).is_ok());
+        let error = interpreter.interpret(vec![expression_statement("x + missing")]).err().unwrap();
+        assert_eq!(error.message, "Variable 'missing' not found");
+    }
+
+    #[test]
+    fn errors_name_the_type_of_a_value() {
+        assert_eq!(error_message("uppercase 3", "TypeError"), "uppercase expects a string, found int 3");
+        assert_eq!(error_message("-\"x\"", "TypeError"), "Unsupported operand for unary '-': string \"x\"");
+        assert_eq!(error_message("true && 1", "TypeError"), "Right operand of '&&' must be a boolean, found int 1");
+        assert_eq!(error_message("1 + true", "TypeError"), "Type mismatch or unsupported operation between int 1 and boolean true");
+    }
+
+    #[test]
+    fn long_values_are_cut_short_in_errors() {
+        let message = error_message("-\"abcdefghijklmnopqrstuvwxyzabcdefghijklmnopqrstuvwxyz\"", "TypeError");
+        assert_eq!(message, "Unsupported operand for unary '-': string \"abcdefghijklmnopqrstuvwxyzabcdefghijklm...");
+    }
+
+    #[test]
+    fn if_else_if_chains() {
+        let program = |x: i32| format!(
+            "let x = {}\nlet result = \"\"\nif (x > 10) {{ result = \"big\" }} else if (x > 3) {{ result = \"mid\" }} else {{ result = \"small\" }}",
+            x,
+        );
+        for (x, expected) in [(20, "big"), (5, "mid"), (1, "small")] {
+            assert_eq!(run_for_result(&program(x)).unwrap().to_string(), expected);
+        }
+        let error = run("if (1) { }").err().unwrap();
+        assert_eq!((error.kind.as_str(), error.message.as_str()), ("TypeError", "Condition expression must evaluate to a boolean"));
+    }
+
+    #[test]
+    fn while_with_break_and_continue() {
+        let program = "let i = 0\nlet result = \"\"\nwhile (i < 10) {\n i = i + 1\n if (i == 2) { continue }\n if (i == 5) { break }\n result = `${result}${i}`\n}";
+        assert_eq!(run_for_result(program).unwrap().to_string(), "134");
+        let nested = "let result = 0\nwhile (result < 3) {\n while (true) { break }\n result = result + 1\n}";
+        assert_eq!(run_for_result(nested).unwrap().to_string(), "3");
+    }
+
+    #[test]
+    fn counted_and_for_in_loops() {
+        assert_eq!(run_for_result("let result = \"\"\nloop (i, 0, 3) { result = `${result}${i}` }").unwrap().to_string(), "0123");
+        assert_eq!(run_for_result("let result = \"\"\nfor i in 1..4 { result = `${result}${i}` }").unwrap().to_string(), "123");
+        assert_eq!(run_for_result("let result = \"\"\nfor c in \"ab\" { result = `${c}${result}` }").unwrap().to_string(), "ba");
+        assert_eq!(run("for x in 5 { }").err().unwrap().message, "Cannot iterate over int 5");
+        assert_eq!(run("loop (i, 0, \"3\") { }").err().unwrap().message, "Loop end must be a number, found string \"3\"");
+    }
+
+    #[test]
+    fn functions_return_and_call() {
+        let program = "func add(a, b) { return a + b }\nlet result = call add(2, 3) + add(1, 1)";
+        assert_eq!(run_for_result(program).unwrap().to_string(), "7");
+        let error = run("func add(a, b) { return a + b }\nadd(1)").err().unwrap();
+        assert_eq!(error.message, "Function 'add' expects 2 argument(s) but got 1");
+        assert_eq!(error_message("missing(1)", "NameError"), "Function 'missing' not found");
+    }
+
+    #[test]
+    fn deep_recursion() {
+        let sum = "func sum(n) {\n if (n == 0) { return 0 }\n return n + sum(n - 1)\n}\nlet result = sum(900)";
+        assert_eq!(on_main_stack(move || run_for_result(sum).unwrap().to_string()), "405450");
+        let (kind, message, trace) = on_main_stack(|| {
+            let error = run("func f() { return f() }\nf()").err().unwrap();
+            (error.kind.clone(), error.message.clone(), error.trace_lines())
+        });
+        assert_eq!(kind, "StackOverflowError");
+        assert_eq!(message, "stack overflow: more than 1000 nested function calls in 'f'");
+        assert_eq!(trace, [
+            "'f' called at line 1, column 19 (repeated 999 times)",
+            "'f' called at line 2, column 1",
+        ]);
+    }
+
+    #[test]
+    fn function_table() {
+        let listed = run_for_result("func greet(name) { }\nlet result = funcs()").unwrap().to_string();
+        assert!(listed.contains("\"greet/1\"") && listed.contains("\"len/1\""), "{}", listed);
+        let error = run("func a() { }\ndelfunc a\na()").err().unwrap();
+        assert_eq!((error.kind.as_str(), error.message.as_str()), ("NameError", "Function 'a' not found"));
+        let cleared = run_for_result("func a() { }\nfunc b() { }\ndelfunc all\nlet result = funcs()").unwrap().to_string();
+        assert!(!cleared.contains("\"a/0\"") && !cleared.contains("\"b/0\"") && cleared.contains("\"len/1\""), "{}", cleared);
+    }
+
+    #[test]
+    fn lexical_scopes() {
+        let shadowed = "let result = 1\nif (true) { let result = 2 }\nfunc f() { let result = 3 }\nf()";
+        assert_eq!(run_for_result(shadowed).unwrap().to_string(), "1");
+        let assigned = "let result = 1\nif (true) { result = 2 }";
+        assert_eq!(run_for_result(assigned).unwrap().to_string(), "2");
+        let error = run("if (true) { let inner = 1 }\nshow inner").err().unwrap();
+        assert_eq!((error.kind.as_str(), error.message.as_str()), ("NameError", "Variable 'inner' not found"));
+        let error = run("make k = 1\nk = 2").err().unwrap();
+        assert_eq!(error.message, "Cannot assign to constant 'k' declared at line 1");
+        let undeclared = "func f() { if (true) { total = 5 } }\nf()\nlet result = total";
+        assert_eq!(run_for_result(undeclared).unwrap().to_string(), "5");
+    }
+
+    #[test]
+    fn closures_keep_their_scope() {
+        let counter = "func makeCounter() {\n let count = 0\n return func() {\n count = count + 1\n return count\n }\n}\n\
+            let next = makeCounter()\nnext()\nlet result = next()";
+        assert_eq!(run_for_result(counter).unwrap().to_string(), "2");
+        let adder = "func adder(n) { return func(x) { return x + n } }\nlet result = adder(1)(2)";
+        assert_eq!(run_for_result(adder).unwrap().to_string(), "3");
+    }
+
+    #[test]
+    fn anonymous_functions_are_values() {
+        assert_eq!(run_for_result("let add = func(a, b) { return a + b }\nlet result = add(2, 3)").unwrap().to_string(), "5");
+        assert_eq!(error_message("func(a) { return a }(1, 2)", "TypeError"), "Function 'anonymous' expects 1 argument(s) but got 2");
+        let named = "let add = 0\nadd = func(a, b) { return a + b }\nlet result = `${add}`";
+        assert_eq!(run_for_result(named).unwrap().to_string(), "<func add/2>");
+        assert_eq!(run("let x = 1\nx()").err().unwrap().message, "'x' is not a function, it holds int 1");
+        assert_eq!(error_message("true()", "TypeError"), "boolean true is not a function");
+    }
+
+    #[test]
+    fn array_literals_indexing_and_commands() {
+        let program = "let result = [3, \"a\", [true]]\narradd result 4\narrdel result 0\nlet size = arrsize result";
+        let interpreter = run(program).unwrap();
+        let environment = interpreter.environment.borrow();
+        assert_eq!(environment.get("result").unwrap().to_string(), "[\"a\",[true],4]");
+        assert_eq!(environment.get("size").unwrap().to_string(), "3");
+        assert_eq!(evaluate("[[1, 2], [3]][0][1]").unwrap().to_string(), "2");
+        assert!(run("let a = [1]\narr a[0]").is_ok());
+        let error = run("let a = [1]\narr a[1]").err().unwrap();
+        assert_eq!((error.kind.as_str(), error.message.as_str()), ("IndexError", "Index 1 is out of bounds for an array of length 1"));
+        assert_eq!(error_message("[1][\"0\"]", "TypeError"), "Array index must be an integer, found string \"0\"");
+        assert_eq!(error_message("arrsize true", "TypeError"), "Expected an array, found boolean true");
+    }
+
+    #[test]
+    fn object_literals_and_member_access() {
+        assert_eq!(evaluate("{a: 1, \"b c\": [2]}").unwrap().to_string(), "{\"a\":1,\"b c\":[2]}");
+        assert_eq!(evaluate("{a: {b: \"x\"}}.a.b").unwrap().to_string(), "x");
+        assert_eq!(evaluate("{a: 1, b: 2} == {b: 2, a: 1}").unwrap(), Value::Boolean(true));
+        assert_eq!(evaluate("{a: 1}.b").unwrap(), Value::Null);
+        assert_eq!(run_for_result("let result = {a: 1}\ndelkey result.a\nresult = result.a").unwrap(), Value::Null);
+        assert_eq!(error_message("true.a", "TypeError"), "Cannot index into boolean true");
+        // Keywords and reserved words can be keys too
+        let keywords = "let o = {show: 1, new : 2}\no.new = 3\nlet result = [o.show, o?.new, o.if]";
+        assert_eq!(run_for_result(keywords).unwrap().to_string(), "[1,3,void]");
+    }
+
+    #[test]
+    fn objects_that_contain_themselves() {
+        let result = run_for_result("let result = {a: 1}\nresult.self = result").unwrap();
+        assert_eq!(result.to_string(), "{\"a\":1,\"self\":{...}}");
+        let other = run_for_result("let result = {a: 1}\nresult.self = result").unwrap();
+        assert_eq!(result, other);
+        let error = run("let o = {a: 1}\no.self = o\nshow tojson o").err().unwrap();
+        assert_eq!(error.kind, "ValueError");
+        assert_eq!(error.message, "Cannot convert an object that contains itself to JSON");
+    }
+
+    #[test]
+    fn deleting_an_array_from_itself() {
+        let error = run("let a = [1]\nlet b = [1]\narradd a b\narrdel a a").err().unwrap();
+        assert_eq!((error.kind.as_str(), error.message.as_str()), ("ValueError", "array [1,[1]] not found in the array"));
+        let result = run_for_result("let result = [1]\nlet b = [2]\narradd result b\narradd result result\narrdel result result\narrdel result b");
+        assert_eq!(result.unwrap().to_string(), "[1]");
+    }
+
+    #[test]
+    fn arrays_that_contain_themselves() {
+        let result = run_for_result("let result = [1]\narradd result result").unwrap();
+        assert_eq!(result.to_string(), "[1,[...]]");
+        let other = run_for_result("let result = [1]\narradd result result").unwrap();
+        assert_eq!(result, other);
+        let error = run("let a = [1]\narradd a a\nshow tojson a").err().unwrap();
+        assert_eq!(error.kind, "ValueError");
+        assert_eq!(error.message, "Cannot convert an array that contains itself to JSON");
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let json = "{\"a\":[1,2.5,\"x\",true,null],\"big\":123456789012345678901234567890}";
+        assert_eq!(evaluate(&format!("tojson parjson {:?}", json)).unwrap(), Value::String(json.to_string()));
+    }
+
+    #[test]
+    fn json_numbers_out_of_range() {
+        assert_eq!(error_message("parjson \"1.5e400\"", "ValueError"), "Invalid JSON at line 1, column 1: number 1.5e400 is out of range");
+        assert_eq!(
+            error_message("parjson \"[\\\"1e999\\\", {\\\"a\\\":\\n  -2e400}]\"", "ValueError"),
+            "Invalid JSON at line 2, column 3: number -2e400 is out of range",
+        );
+        assert_eq!(error_message("parjson \"[1,\"", "ValueError"), "Invalid JSON at line 1, column 3: EOF while parsing a value");
+    }
+
+    #[test]
+    fn for_in_walks_a_string_by_grapheme() {
+        let result = run_for_result("let result = []\nfor c in \"e\\u{301}🎉a\" { arradd result c }").unwrap();
+        assert_eq!(result.to_string(), "[\"e\u{301}\",\"🎉\",\"a\"]");
+    }
+
+    // The "Mixing Types" table in the tutorial, one row per case
+    #[test]
+    fn mixed_type_operations() {
+        let cases: &[(&str, &str)] = &[
+            ("\"a\" + \"b\"", "ab"),
+            ("\"a\" + 1", "a1"),
+            ("1 + \"a\"", "1a"),
+            ("1.5 + \"x\"", "1.5x"),
+            ("\"total: \" + 30", "total: 30"),
+            ("\"b\" > \"a\"", "true"),
+            ("\"apple\" < \"banana\"", "true"),
+            ("\"ab\" <= \"ab\"", "true"),
+            ("\"Z\" < \"a\"", "true"),
+            ("1 == \"1\"", "true"),
+            ("1 == \"1.0\"", "true"),
+            ("1 == \" 1 \"", "true"),
+            ("0 != \"\"", "true"),
+            ("0 == \"zero\"", "false"),
+            ("\"1\" == 1", "true"),
+            ("1 === \"1\"", "false"),
+            ("1 !== \"1\"", "true"),
+            ("1 === 1", "true"),
+            ("\"a\" === \"a\"", "true"),
+            ("true == \"true\"", "false"),
+        ];
+        for (expression, expected) in cases {
+            match evaluate(expression) {
+                Ok(value) => assert_eq!(value.to_string(), *expected, "{}", expression),
+                Err(error) => panic!("{} failed: {}", expression, error.message),
+            }
+        }
+    }
+
+    #[test]
+    fn mixed_type_operations_that_are_errors() {
+        let cases: &[(&str, &str)] = &[
+            ("\"1\" < 2", "string \"1\" and int 2"),
+            ("2 >= \"1\"", "int 2 and string \"1\""),
+            ("\"a\" - 1", "string \"a\" and int 1"),
+            ("\"a\" * 2", "string \"a\" and int 2"),
+            ("true + 1", "boolean true and int 1"),
+            ("[1] + \"a\"", "array [1] and string \"a\""),
+        ];
+        for (expression, operands) in cases {
+            let message = error_message(expression, "TypeError");
+            assert_eq!(message, format!("Type mismatch or unsupported operation between {}", operands), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn void_values() {
+        assert_eq!(evaluate("void").unwrap(), Value::Null);
+        assert_eq!(evaluate("isvoid {a: 1}.b").unwrap(), Value::Boolean(true));
+        assert_eq!(evaluate("void ?? 2").unwrap(), Value::Int(2));
+        assert_eq!(evaluate("1 ?? 2").unwrap(), Value::Int(1));
+        assert_eq!(run_for_result("func f() { }\nlet result = f()").unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn out_of_bounds_reads_are_errors() {
+        assert_eq!(error_message("[1, 2][-1]", "IndexError"), "Index -1 is out of bounds for an array of length 2");
+        assert_eq!(error_message("[1, 2][10]", "IndexError"), "Index 10 is out of bounds for an array of length 2");
+        assert_eq!(evaluate("[1, 2]?.[10]").unwrap(), Value::Null);
+        assert_eq!(evaluate("[1, 2]?.[-1]").unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn optional_access_short_circuits_the_chain() {
+        assert_eq!(evaluate("{a: 1}?.b.c").unwrap(), Value::Null);
+        assert_eq!(evaluate("void?.a[0].b").unwrap(), Value::Null);
+        assert_eq!(evaluate("void?.f(no_such_variable)").unwrap(), Value::Null);
+        assert_eq!(evaluate("{f: func() { return 7 }}?.f()").unwrap(), Value::Int(7));
+        assert_eq!(error_message("{a: 1}.b.c", "TypeError"), "Cannot index into void");
+    }
+
+    #[test]
+    fn catch_and_finally() {
+        let program = "let result = []\n\
+            func inner() { throw error(\"ValueError\", \"bad\") }\n\
+            func outer() { inner() }\n\
+            try { outer() } catch (e) { arradd result e.kind\narradd result e.message\narradd result len(e.trace) }\n\
+            finally { arradd result \"finally\" }";
+        assert_eq!(run_for_result(program).unwrap().to_string(), "[\"ValueError\",\"bad\",2,\"finally\"]");
+        let caught = "let result = void\ntry { let x = 1 // 0 } catch (e) { result = e.kind }";
+        assert_eq!(run_for_result(caught).unwrap(), Value::String("ZeroDivisionError".to_string()));
+        let thrown = "let result = void\ntry { throw \"plain\" } catch (e) { result = e }";
+        assert_eq!(run_for_result(thrown).unwrap(), Value::String("plain".to_string()));
+        let payload = "let result = void\nfunc f() { throw {code: 1} }\ntry { f() } catch (e) { result = e.code }";
+        assert_eq!(run_for_result(payload).unwrap(), Value::Int(1));
+        let error = run("throw {code: 1}").err().unwrap();
+        assert_eq!((error.kind.as_str(), error.message.as_str()), ("Error", "{\"code\":1}"));
+    }
+
+    #[test]
+    fn finally_runs_on_every_exit() {
+        let returned = "let result = []\nfunc f() { try { return 1 } finally { arradd result \"f\" } }\narradd result f()";
+        assert_eq!(run_for_result(returned).unwrap().to_string(), "[\"f\",1]");
+        let overridden = "func g() { try { return 1 } finally { return 2 } }\nlet result = g()";
+        assert_eq!(run_for_result(overridden).unwrap(), Value::Int(2));
+        let error = run("let log = []\ntry { throw error(\"KeyError\", \"k\") } finally { arradd log 1 }").err().unwrap();
+        assert_eq!((error.kind.as_str(), error.message.as_str()), ("KeyError", "k"));
     }
 }
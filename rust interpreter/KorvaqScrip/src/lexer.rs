@@ -1,232 +1,1039 @@
 use std::collections::HashSet;
+use std::fmt;
+use std::str::Chars;
+use crate::diagnostics::Diagnostic;
+use crate::span::Span;
 use crate::token_type::TokenType;
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub value: String,
     pub token_type: TokenType,
+    pub span: Span,
+    pub trivia: Vec<Trivia>, // Comments kept from right before this token
 }
 
+// Source text that isn't a token itself but is kept with the token after it
+#[derive(Debug, Clone)]
+pub enum Trivia {
+    // `/// text`, with the slashes and one leading space removed
+    DocComment { text: String, span: Span },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    ReservedWord(String),
+    MalformedNumber(String),
+    InvalidEscape(String),
+    UnterminatedComment,
+}
+
+#[derive(Debug, Clone)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            LexErrorKind::UnexpectedCharacter(c) => write!(f, "Unexpected character: '{}'", c),
+            LexErrorKind::UnterminatedString => write!(f, "Unterminated string literal"),
+            LexErrorKind::ReservedWord(word) => write!(f, "Using a restricted keyword: {}", word),
+            LexErrorKind::MalformedNumber(reason) => write!(f, "Malformed number literal: {}", reason),
+            LexErrorKind::InvalidEscape(reason) => write!(f, "Invalid escape sequence: {}", reason),
+            LexErrorKind::UnterminatedComment => write!(f, "Unterminated block comment"),
+        }
+    }
+}
+
+impl From<LexError> for Diagnostic {
+    fn from(error: LexError) -> Self {
+        let diagnostic = Diagnostic::error(error.kind.code(), error.to_string(), error.span);
+        match error.kind {
+            LexErrorKind::UnexpectedCharacter(_) => diagnostic.with_label("not valid here"),
+            LexErrorKind::UnterminatedString => diagnostic
+                .with_label("string starts here")
+                .with_help("add the closing quote"),
+            LexErrorKind::ReservedWord(word) => diagnostic
+                .with_label("reserved word")
+                .with_help(format!("'{}' is reserved for future use, pick another name", word)),
+            LexErrorKind::MalformedNumber(_) => diagnostic
+                .with_label("in this number")
+                .with_help("numbers look like `42`, `3.14`, `1e-9`, `1_000`, `0xff`, `0b1010` or `0o17`"),
+            LexErrorKind::InvalidEscape(_) => diagnostic
+                .with_label("in this escape")
+                .with_help("supported escapes are \\n \\t \\r \\0 \\\\ \\\" \\' \\` \\$ and \\u{...}, or use a raw string like r\"...\""),
+            LexErrorKind::UnterminatedComment => diagnostic
+                .with_label("comment starts here")
+                .with_help("close it with `*/`, block comments can be nested so every `/*` needs one"),
+        }
+    }
+}
+
+impl LexErrorKind {
+    fn code(&self) -> &'static str {
+        match self {
+            LexErrorKind::UnexpectedCharacter(_) => "E0001",
+            LexErrorKind::UnterminatedString => "E0002",
+            LexErrorKind::ReservedWord(_) => "E0003",
+            LexErrorKind::MalformedNumber(_) => "E0004",
+            LexErrorKind::InvalidEscape(_) => "E0005",
+            LexErrorKind::UnterminatedComment => "E0006",
+        }
+    }
+}
+
+// Where the lexer is inside a backtick template string
+enum TemplateMode {
+    Text { start: Span },            // Reading literal text, `start` is the opening backtick
+    Interpolation { depth: usize },  // Inside `${ ... }`, `depth` counts nested braces
+}
+
+// The lexer walks the input one `char` at a time, so multibyte text is never
+// split and every character is looked at once. `pos` is the byte offset of
+// `chars`, `line` and `column` count characters and are 1-based.
 pub struct Lexer<'a> {
     pos: usize,
+    line: usize,
+    column: usize,
     input: &'a str,
+    chars: Chars<'a>, // The input that hasn't been consumed yet
     restricted_keywords: HashSet<String>,
+    templates: Vec<TemplateMode>,
+    trivia: Vec<Trivia>, // Collected for the next token
+    previous: Option<TokenType>, // Type of the last token returned
+    after_operand: bool,         // Whether that token ends an operand on the current line
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
+        Lexer::starting_at(input, 0, 1)
+    }
+
+    // Lex `input` from byte offset `start`, which is at the beginning of line
+    // `line`. The REPL keeps every line typed so far in one source, spans
+    // from earlier inputs stay valid in it.
+    pub fn starting_at(input: &'a str, start: usize, line: usize) -> Self {
         let restricted_keywords = [
-            "var", "const", "for", "switch", "case", "break",
-            "continue", "default", "class", "extends", "super", "this",
-            "typeof", "instanceof", "delete", "new", "in",
-            "try", "catch", "finally", "throw", "debugger",
+            "var", "const", "switch", "case",
+            "default", "class", "extends", "super", "this",
+            "typeof", "instanceof", "delete", "new",
+            "debugger",
         ].iter().cloned().map(String::from).collect();
 
         Lexer {
-            pos: 0,
+            pos: start,
+            line,
+            column: 1,
             input,
+            chars: input[start..].chars(),
             restricted_keywords,
+            templates: Vec::new(),
+            trivia: Vec::new(),
+            previous: None,
+            after_operand: false,
         }
     }
 
-    pub fn next_token(&mut self) -> Option<Token> {
-        while self.pos < self.input.len() {
-            let current_char = self.input[self.pos..].chars().next().unwrap();
+    pub fn source(&self) -> &'a str {
+        self.input
+    }
 
+    // Zero-width span at the current position, used to mark where a token
+    // starts and where the input ends
+    pub fn position(&self) -> Span {
+        Span::new(self.pos, self.pos, self.line, self.column)
+    }
+
+    pub fn next_token(&mut self) -> Result<Option<Token>, LexError> {
+        // Inside a template string whitespace and comments are part of the text
+        if let Some(TemplateMode::Text { .. }) = self.templates.last() {
+            return self.read_template_text().map(Some);
+        }
+
+        while let Some(current_char) = self.peek(0) {
             // Skip whitespace
             if current_char.is_whitespace() {
-                self.pos += 1;
+                if current_char == '\n' {
+                    self.after_operand = false;
+                }
+                self.advance(1);
                 continue;
             }
 
-            // Check for single-line comments
-            if current_char == '/' && self.input.get(self.pos + 1..).map_or(false, |s| s.starts_with('/')) {
-                while self.pos < self.input.len() && self.input.chars().nth(self.pos).unwrap() != '\n' {
-                    self.pos += 1;
+            // Check for single-line comments. `///` doc comments are kept as
+            // trivia on the next token, other comments are dropped. Right
+            // after an operand on the same line `//` is integer division.
+            if current_char == '/' && self.peek(1) == Some('/') && !self.after_operand {
+                let start = self.position();
+                let is_doc = self.peek(2) == Some('/') && self.peek(3) != Some('/');
+                let mut text = String::new();
+                while let Some(c) = self.peek(0) {
+                    if c == '\n' {
+                        break;
+                    }
+                    text.push(c);
+                    self.advance(1);
+                }
+                if is_doc {
+                    let doc = &text[3..];
+                    self.trivia.push(Trivia::DocComment {
+                        text: doc.strip_prefix(' ').unwrap_or(doc).trim_end().to_string(),
+                        span: Span { end: self.pos, ..start },
+                    });
                 }
-                self.pos += 1; // Move past the newline
+                self.advance(1); // Move past the newline
                 continue;
             }
 
-            // Check for boolean literals
-            if self.input.get(self.pos..).map_or(false, |s| s.starts_with("true")) {
-                self.pos += 4; // Move position past "true"
-                return Some(Token { value: "true".to_string(), token_type: TokenType::BooleanLiteral });
-            }
-            if self.input.get(self.pos..).map_or(false, |s| s.starts_with("false")) {
-                self.pos += 5; // Move position past "false"
-                return Some(Token { value: "false".to_string(), token_type: TokenType::BooleanLiteral });
+            // Check for block comments
+            if current_char == '/' && self.peek(1) == Some('*') {
+                self.skip_block_comment()?;
+                continue;
             }
 
+            let start = self.position();
+
             // Check for numbers, strings, keywords, operators, and punctuation
-            if current_char.is_digit(10) {
-                return Some(self.read_number());
+            if current_char.is_ascii_digit() {
+                return self.read_number().map(Some);
+            }
+            if current_char == '"' || current_char == '\'' {
+                return self.read_string(current_char).map(Some);
             }
-            if current_char == '"' || current_char == '\'' || current_char == '`' {
-                return Some(self.read_string(current_char));
+            if current_char == '`' {
+                self.advance(1);
+                self.templates.push(TemplateMode::Text { start });
+                return Ok(Some(self.token_from(start, "`", TokenType::TemplateStart)));
+            }
+            if current_char == 'r' && matches!(self.peek(1), Some('"') | Some('\'') | Some('#')) {
+                return self.read_raw_string().map(Some);
             }
             if current_char.is_alphabetic() || current_char == '_' {
-                return Some(self.read_keyword_or_identifier());
+                return self.read_keyword_or_identifier().map(Some);
             }
 
             // Handle operators and punctuation
             if current_char == '=' {
-                self.pos += 1;
-                if self.pos < self.input.len() && self.input.chars().nth(self.pos).unwrap() == '=' {
-                    self.pos += 1;
-                    if self.pos < self.input.len() && self.input.chars().nth(self.pos).unwrap() == '=' {
-                        self.pos += 1;
-                        return Some(Token { value: "===" .to_string(), token_type: TokenType::Equals });
+                self.advance(1);
+                if self.peek(0) == Some('=') {
+                    self.advance(1);
+                    if self.peek(0) == Some('=') {
+                        self.advance(1);
+                        return Ok(Some(self.token_from(start, "===", TokenType::EqualEquals)));
                     }
-                    return Some(Token { value: "==" .to_string(), token_type: TokenType::Equals });
+                    return Ok(Some(self.token_from(start, "==", TokenType::EqualEquals)));
                 }
-                return Some(Token { value: "=" .to_string(), token_type: TokenType::Equals });
+                return Ok(Some(self.token_from(start, "=", TokenType::Equals)));
             }
             if current_char == '(' {
-                self.pos += 1;
-                return Some(Token { value: "(".to_string(), token_type: TokenType::OpenParen });
+                self.advance(1);
+                return Ok(Some(self.token_from(start, "(", TokenType::OpenParen)));
             }
             if current_char == ')' {
-                self.pos += 1;
-                return Some(Token { value: ")".to_string(), token_type: TokenType::CloseParen });
+                self.advance(1);
+                return Ok(Some(self.token_from(start, ")", TokenType::CloseParen)));
             }
             // Ignore semicolon
             if current_char == ';' {
-                self.pos += 1;
+                self.after_operand = false;
+                self.advance(1);
                 continue; // Skip semicolon
             }
             if current_char == ':' {
-                self.pos += 1;
-                return Some(Token { value: ":".to_string(), token_type: TokenType::Colon });
+                self.advance(1);
+                return Ok(Some(self.token_from(start, ":", TokenType::Colon)));
             }
             if current_char == '{' {
-                self.pos += 1;
-                return Some(Token { value: "{".to_string(), token_type: TokenType::OpenBrace });
+                if let Some(TemplateMode::Interpolation { depth }) = self.templates.last_mut() {
+                    *depth += 1;
+                }
+                self.advance(1);
+                return Ok(Some(self.token_from(start, "{", TokenType::OpenBrace)));
             }
             if current_char == '}' {
-                self.pos += 1;
-                return Some(Token { value: "}".to_string(), token_type: TokenType::CloseBrace });
+                match self.templates.last_mut() {
+                    // This brace closes a `${`, go back to reading template text
+                    Some(TemplateMode::Interpolation { depth: 0 }) => {
+                        self.templates.pop();
+                        self.advance(1);
+                        return Ok(Some(self.token_from(start, "}", TokenType::TemplateExprEnd)));
+                    }
+                    Some(TemplateMode::Interpolation { depth }) => *depth -= 1,
+                    _ => {}
+                }
+                self.advance(1);
+                return Ok(Some(self.token_from(start, "}", TokenType::CloseBrace)));
             }
 
             // Handle array syntax
             if current_char == '[' {
-                self.pos += 1;
-                return Some(Token { value: "[".to_string(), token_type: TokenType::OpenBracket });
+                self.advance(1);
+                return Ok(Some(self.token_from(start, "[", TokenType::OpenBracket)));
             }
             if current_char == ']' {
-                self.pos += 1;
-                return Some(Token { value: "]".to_string(), token_type: TokenType::CloseBracket });
+                self.advance(1);
+                return Ok(Some(self.token_from(start, "]", TokenType::CloseBracket)));
             }
             if current_char == ',' {
-                self.pos += 1;
-                return Some(Token { value: ",".to_string(), token_type: TokenType::Comma });
+                self.advance(1);
+                return Ok(Some(self.token_from(start, ",", TokenType::Comma)));
+            }
+            if current_char == '.' && self.peek(1) == Some('.') {
+                self.advance(2); // Move past '..'
+                return Ok(Some(self.token_from(start, "..", TokenType::DotDot)));
+            }
+            if current_char == '.' {
+                self.advance(1);
+                return Ok(Some(self.token_from(start, ".", TokenType::Dot)));
             }
 
-            if current_char == '*' && self.input.get(self.pos + 1..).map_or(false, |s| s.starts_with("*")) {
-                self.pos += 2; // Move past '**'
-                return Some(Token { value: "**".to_string(), token_type: TokenType::BinaryOperator });
+            // `//` right after an operand divides and rounds down
+            if current_char == '/' && self.peek(1) == Some('/') {
+                self.advance(2); // Move past '//'
+                return Ok(Some(self.token_from(start, "//", TokenType::BinaryOperator)));
+            }
+
+            if current_char == '*' && self.peek(1) == Some('*') {
+                self.advance(2); // Move past '**'
+                return Ok(Some(self.token_from(start, "**", TokenType::BinaryOperator)));
             }
 
             // Handle binary operators
             if ['+', '-', '*', '/', '%', '^'].contains(&current_char) {
-                return Some(self.read_binary_operator(current_char));
+                return Ok(Some(self.read_binary_operator(current_char)));
             }
 
             // Handle comparison operators
             if current_char == '>' {
-                return Some(self.read_comparison_operator('>'));
+                return Ok(Some(self.read_comparison_operator('>')));
             }
             if current_char == '<' {
-                return Some(self.read_comparison_operator('<'));
+                return Ok(Some(self.read_comparison_operator('<')));
             }
 
             // Handle logical operators
-            if current_char == '&' && self.input.get(self.pos + 1..).map_or(false, |s| s.starts_with("&")) {
-                self.pos += 2; // Move past '&&'
-                return Some(Token { value: "&&".to_string(), token_type: TokenType::LogicalAnd });
+            if current_char == '&' && self.peek(1) == Some('&') {
+                self.advance(2); // Move past '&&'
+                return Ok(Some(self.token_from(start, "&&", TokenType::LogicalAnd)));
+            }
+            if current_char == '|' && self.peek(1) == Some('|') {
+                self.advance(2); // Move past '||'
+                return Ok(Some(self.token_from(start, "||", TokenType::LogicalOr)));
             }
-            if current_char == '|' && self.input.get(self.pos + 1..).map_or(false, |s| s.starts_with("|")) {
-                self.pos += 2; // Move past '||'
-                return Some(Token { value: "||".to_string(), token_type: TokenType::LogicalOr });
+
+            // Handle `??` and `?.`
+            if current_char == '?' && self.peek(1) == Some('?') {
+                self.advance(2); // Move past '??'
+                return Ok(Some(self.token_from(start, "??", TokenType::NullCoalesce)));
+            }
+            if current_char == '?' && self.peek(1) == Some('.') {
+                self.advance(2); // Move past '?.'
+                return Ok(Some(self.token_from(start, "?.", TokenType::QuestionDot)));
+            }
+
+            // Handle not equal operators and logical not
+            if current_char == '!' {
+                if self.peek(1) == Some('=') && self.peek(2) == Some('=') {
+                    self.advance(3); // Move past '!=='
+                    return Ok(Some(self.token_from(start, "!==", TokenType::NotEquals)));
+                }
+                if self.peek(1) == Some('=') {
+                    self.advance(2); // Move past '!='
+                    return Ok(Some(self.token_from(start, "!=", TokenType::NotEquals)));
+                }
+                self.advance(1); // Move past '!'
+                return Ok(Some(self.token_from(start, "!", TokenType::Bang)));
             }
 
-            // Handle not equal operator
-            if current_char == '!' && self.input.get(self.pos + 1..).map_or(false, |s| s.starts_with("=")) {
-                self.pos += 2; // Move past '!='
-                return Some(Token { value: "!=".to_string(), token_type: TokenType::NotEquals });
+            self.advance(1);
+            return Err(LexError {
+                kind: LexErrorKind::UnexpectedCharacter(current_char),
+                span: Span { end: self.pos, ..start },
+            });
+        }
+
+        Ok(None)
+    }
+
+    // Consume one character, keeping the position, line and column in sync
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    // Step `count` characters forward
+    fn advance(&mut self, count: usize) {
+        for _ in 0..count {
+            if self.bump().is_none() {
+                return;
             }
+        }
+    }
+
+    // Look `offset` characters ahead without consuming anything
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.clone().nth(offset)
+    }
+
+    // The input that hasn't been consumed yet
+    fn rest(&self) -> &'a str {
+        self.chars.as_str()
+    }
 
-            panic!("Unexpected character: {}", current_char);
+    // Finish a token that started at `start` and ends at the current position
+    fn token_from(&mut self, start: Span, value: &str, token_type: TokenType) -> Token {
+        self.after_operand = matches!(
+            token_type,
+            TokenType::Number | TokenType::String | TokenType::Identifier | TokenType::BooleanLiteral
+                | TokenType::Void | TokenType::CloseParen | TokenType::CloseBracket | TokenType::TemplateEnd
+        );
+        self.previous = Some(token_type.clone());
+        Token {
+            value: value.to_string(),
+            token_type,
+            span: Span { end: self.pos, ..start },
+            trivia: std::mem::take(&mut self.trivia),
         }
+    }
 
-        None
+    // Skip a `/* ... */` comment. They nest, so `/* a /* b */ c */` is one comment.
+    fn skip_block_comment(&mut self) -> Result<(), LexError> {
+        let start = self.position();
+        self.advance(2); // Move past '/*'
+
+        let mut depth = 1;
+        while depth > 0 {
+            match (self.peek(0), self.peek(1)) {
+                (Some('/'), Some('*')) => {
+                    depth += 1;
+                    self.advance(2);
+                }
+                (Some('*'), Some('/')) => {
+                    depth -= 1;
+                    self.advance(2);
+                }
+                (Some(_), _) => self.advance(1),
+                (None, _) => {
+                    return Err(LexError {
+                        kind: LexErrorKind::UnterminatedComment,
+                        span: Span { end: start.start + 2, ..start },
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Whether the word just read is an object key, so `o.new` or `{show: 1}`
+    fn at_key(&self) -> bool {
+        matches!(self.previous, Some(TokenType::Dot | TokenType::QuestionDot))
+            || self.rest().trim_start().starts_with(':')
     }
 
-    fn read_keyword_or_identifier(&mut self) -> Token {
+    fn read_keyword_or_identifier(&mut self) -> Result<Token, LexError> {
+        let start = self.position();
         let mut id_str = String::new();
 
         // Allow the first character to be a letter or underscore
-        if self.input[self.pos..].chars().next().unwrap().is_alphabetic() || self.input[self.pos..].chars().next().unwrap() == '_' {
-            id_str.push(self.input[self.pos..].chars().next().unwrap());
-            self.pos += 1;
+        let first_char = self.peek(0).unwrap_or_default();
+        if first_char.is_alphabetic() || first_char == '_' {
+            id_str.push(first_char);
+            self.advance(1);
         } else {
-            panic!("Invalid identifier start: {} at position {}", self.input[self.pos..].chars().next().unwrap(), self.pos);
+            self.advance(1);
+            return Err(LexError {
+                kind: LexErrorKind::UnexpectedCharacter(first_char),
+                span: Span { end: self.pos, ..start },
+            });
+        }
+
+        // Allow subsequent characters to be letters, digits, underscores or combining marks
+        while let Some(next_char) = self.peek(0) {
+            if !is_identifier_char(next_char) {
+                break;
+            }
+            id_str.push(next_char);
+            self.advance(1);
         }
 
-        // Allow subsequent characters to be letters, digits, or underscores
-        while self.pos < self.input.len() && (self.input[self.pos..].chars().next().unwrap().is_alphanumeric() || self.input[self.pos..].chars().next().unwrap() == '_') {
-            id_str.push(self.input[self.pos..].chars().next().unwrap());
-            self.pos += 1;
+        // A key, after '.' or '?.' or before ':' in an object, may be any word
+        if self.at_key() {
+            return Ok(self.token_from(start, &id_str, TokenType::Identifier));
         }
 
         // Check for restricted keywords
         if self.restricted_keywords.contains(&id_str) && id_str != "let" {
-            panic!("Using a restricted keyword: {}", id_str);
+            return Err(LexError {
+                kind: LexErrorKind::ReservedWord(id_str),
+                span: Span { end: self.pos, ..start },
+            });
         }
 
-        match id_str.as_str() {
-            "let" => Token { value: id_str, token_type: TokenType::Let },
-            "make" => Token { value: id_str, token_type: TokenType::Make },
-            "show" => Token { value: id_str, token_type: TokenType::Show }, 
-            "delvar" => Token { value: id_str, token_type: TokenType::DelVar },
-            "if" => Token { value: id_str, token_type: TokenType::If },
-            "else" => Token { value: id_str, token_type: TokenType::Else },
-            "uppercase" => Token { value: id_str, token_type: TokenType::UpperCase },
-            "lowercase" => Token { value: id_str, token_type: TokenType::LowerCase },
-            _ => Token { value: id_str, token_type: TokenType::Identifier },
+        let token_type = match id_str.as_str() {
+            "let" => TokenType::Let,
+            "make" => TokenType::Make,
+            "show" => TokenType::Show,
+            "delvar" => TokenType::DelVar,
+            "delfunc" => TokenType::DelFunc,
+            "arr" => TokenType::Arr,
+            "arradd" => TokenType::ArrAdd,
+            "arrdel" => TokenType::ArrDel,
+            "arrsize" => TokenType::ArrSize,
+            "delkey" => TokenType::DelKey,
+            "tojson" => TokenType::ToJson,
+            "prettyjson" => TokenType::PrettyJson,
+            "parjson" => TokenType::ParJson,
+            "if" => TokenType::If,
+            "else" => TokenType::Else,
+            "while" => TokenType::While,
+            "break" => TokenType::Break,
+            "continue" => TokenType::Continue,
+            "loop" => TokenType::Loop,
+            "for" => TokenType::For,
+            "in" => TokenType::In,
+            "func" => TokenType::Func,
+            "return" => TokenType::Return,
+            "call" => TokenType::Call,
+            "uppercase" => TokenType::UpperCase,
+            "true" | "false" => TokenType::BooleanLiteral,
+            "void" => TokenType::Void,
+            "try" => TokenType::Try,
+            "catch" => TokenType::Catch,
+            "finally" => TokenType::Finally,
+            "throw" => TokenType::Throw,
+            "isvoid" => TokenType::IsVoid,
+            "lowercase" => TokenType::LowerCase,
+            _ => TokenType::Identifier,
+        };
+        Ok(self.token_from(start, &id_str, token_type))
+    }
+
+    // Numbers can be decimal (`42`, `3.14`, `1e-9`) or prefixed integers
+    // (`0xff`, `0b1010`, `0o17`). Single `_` separators may sit between digits.
+    // The token value is the literal with separators removed.
+    fn read_number(&mut self) -> Result<Token, LexError> {
+        let start = self.position();
+        let mut literal = String::new();
+
+        let radix = match self.rest().get(..2) {
+            Some("0x") | Some("0X") => 16,
+            Some("0b") | Some("0B") => 2,
+            Some("0o") | Some("0O") => 8,
+            _ => 10,
+        };
+
+        if radix != 10 {
+            literal.push_str(&self.rest()[..2].to_lowercase());
+            self.advance(2); // Move past the prefix
+            let digits = self.read_digits(radix, start)?;
+            if digits.is_empty() {
+                return Err(self.malformed_number(start, "expected digits after the prefix"));
+            }
+            literal.push_str(&digits);
+        } else {
+            literal.push_str(&self.read_digits(10, start)?);
+
+            // Fractional part, only when a digit follows the dot. `1..5` is a
+            // range and `1.key` member access, those are left to the parser,
+            // but `1.` and `1.e5` are missing the digits after the point.
+            if self.peek(0) == Some('.') {
+                let exponent_follows = matches!(self.peek(1), Some('e') | Some('E'))
+                    && self.peek(2).is_some_and(|c| c.is_ascii_digit() || c == '+' || c == '-');
+                match self.peek(1) {
+                    Some(c) if c.is_ascii_digit() => {
+                        literal.push('.');
+                        self.advance(1); // Move past '.'
+                        literal.push_str(&self.read_digits(10, start)?);
+                    }
+                    Some('.') => {}
+                    Some(c) if (c.is_alphabetic() || c == '_') && !exponent_follows => {}
+                    _ => {
+                        self.advance(1); // Move past '.'
+                        return Err(self.malformed_number(start, "expected digits after the decimal point"));
+                    }
+                }
+            }
+
+            // Exponent, e.g. `1e9` or `2.5E-3`
+            if matches!(self.peek(0), Some('e') | Some('E')) {
+                literal.push('e');
+                self.advance(1); // Move past 'e'
+                if let Some(sign @ ('+' | '-')) = self.peek(0) {
+                    literal.push(sign);
+                    self.advance(1);
+                }
+                let digits = self.read_digits(10, start)?;
+                if digits.is_empty() {
+                    return Err(self.malformed_number(start, "expected digits in the exponent"));
+                }
+                literal.push_str(&digits);
+            }
+        }
+
+        // `1e400` doesn't fit in a float, rather than quietly being infinity
+        if radix == 10 && literal.contains(['.', 'e']) && !literal.parse::<f64>().is_ok_and(f64::is_finite) {
+            return Err(self.malformed_number(start, "too large for a float"));
+        }
+
+        // A number can't run straight into letters, e.g. `0b102` or `12px`
+        if let Some(c) = self.peek(0) {
+            if c.is_alphanumeric() || c == '_' {
+                while self.peek(0).is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                    self.advance(1);
+                }
+                return Err(self.malformed_number(start, &format!("invalid digit '{}'", c)));
+            }
         }
+
+        Ok(self.token_from(start, &literal, TokenType::Number))
     }
 
-    fn read_number(&mut self) -> Token {
-        let start_pos = self.pos;
-        while self.pos < self.input.len() && self.input[self.pos..].chars().next().unwrap().is_digit(10) {
-            self.pos += 1;
+    // Read a run of digits in `radix`, dropping `_` separators. A separator
+    // must sit between two digits.
+    fn read_digits(&mut self, radix: u32, start: Span) -> Result<String, LexError> {
+        let mut digits = String::new();
+        while let Some(c) = self.peek(0) {
+            if c.is_digit(radix) {
+                digits.push(c);
+                self.advance(1);
+            } else if c == '_' {
+                let next_is_digit = self.peek(1).is_some_and(|n| n.is_digit(radix));
+                if digits.is_empty() || !next_is_digit {
+                    self.advance(1);
+                    return Err(self.malformed_number(start, "misplaced '_' digit separator"));
+                }
+                self.advance(1); // Skip the separator
+            } else {
+                break;
+            }
         }
+        Ok(digits)
+    }
 
-        let number_str = &self.input[start_pos..self.pos];
-        Token { value: number_str.to_string(), token_type: TokenType::Number }
+    fn malformed_number(&self, start: Span, reason: &str) -> LexError {
+        LexError {
+            kind: LexErrorKind::MalformedNumber(reason.to_string()),
+            span: Span { end: self.pos, ..start },
+        }
+    }
+
+    fn read_string(&mut self, quote_char: char) -> Result<Token, LexError> {
+        let start = self.position();
+        self.advance(1); // Skip the opening quote
+        let mut string_content = String::new();
+
+        loop {
+            let c = match self.peek(0) {
+                Some(c) => c,
+                None => {
+                    return Err(LexError {
+                        kind: LexErrorKind::UnterminatedString,
+                        span: Span { end: self.pos, ..start },
+                    });
+                }
+            };
+
+            if c == quote_char {
+                break;
+            }
+            if c == '\\' {
+                match self.read_escape() {
+                    Ok(escaped) => string_content.push(escaped),
+                    Err(error) => {
+                        self.skip_string_rest(quote_char);
+                        return Err(error);
+                    }
+                }
+                continue;
+            }
+
+            string_content.push(c);
+            self.advance(1);
+        }
+
+        self.advance(1); // Skip the closing quote
+        Ok(self.token_from(start, &string_content, TokenType::String))
     }
 
-    fn read_string(&mut self, quote_char: char) -> Token {
-        self.pos += 1; // Skip the opening quote
-        let start_pos = self.pos;
+    // Read the next piece of a template string: a run of text, the `${` that
+    // opens an interpolation, or the closing backtick
+    fn read_template_text(&mut self) -> Result<Token, LexError> {
+        let start = self.position();
 
-        while self.pos < self.input.len() && self.input[self.pos..].chars().next().unwrap() != quote_char {
-            self.pos += 1;
+        if self.rest().starts_with("${") {
+            self.advance(2); // Move past '${'
+            self.templates.push(TemplateMode::Interpolation { depth: 0 });
+            return Ok(self.token_from(start, "${", TokenType::TemplateExprStart));
+        }
+        if self.peek(0) == Some('`') {
+            self.advance(1); // Move past the closing backtick
+            self.templates.pop();
+            return Ok(self.token_from(start, "`", TokenType::TemplateEnd));
         }
 
-        let string_content = &self.input[start_pos..self.pos];
-        self.pos += 1; // Skip the closing quote
-        Token { value: string_content.to_string(), token_type: TokenType::String }
+        let mut text = String::new();
+        loop {
+            match self.peek(0) {
+                None => {
+                    let template_start = match self.templates.pop() {
+                        Some(TemplateMode::Text { start }) => start,
+                        _ => start,
+                    };
+                    return Err(LexError {
+                        kind: LexErrorKind::UnterminatedString,
+                        span: Span { end: self.pos, ..template_start },
+                    });
+                }
+                Some('`') => break,
+                Some('$') if self.peek(1) == Some('{') => break,
+                Some('\\') => match self.read_escape() {
+                    Ok(escaped) => text.push(escaped),
+                    Err(error) => {
+                        self.skip_string_rest('`');
+                        self.templates.pop();
+                        return Err(error);
+                    }
+                },
+                Some(c) => {
+                    text.push(c);
+                    self.advance(1);
+                }
+            }
+        }
+
+        Ok(self.token_from(start, &text, TokenType::TemplateString))
+    }
+
+    // After a bad escape, step over the rest of the string so its closing
+    // quote isn't read as the start of a new one
+    fn skip_string_rest(&mut self, quote_char: char) {
+        while let Some(c) = self.bump() {
+            if c == quote_char {
+                return;
+            }
+            if c == '\\' {
+                self.bump();
+            }
+        }
+    }
+
+    // Read one escape sequence starting at the backslash
+    fn read_escape(&mut self) -> Result<char, LexError> {
+        let start = self.position();
+        self.advance(1); // Skip the backslash
+
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => {
+                return Err(LexError {
+                    kind: LexErrorKind::UnterminatedString,
+                    span: Span { end: self.pos, ..start },
+                });
+            }
+        };
+        self.advance(1);
+
+        let escaped = match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' | '"' | '\'' | '`' | '$' => c,
+            'u' => return self.read_unicode_escape(start),
+            _ => return Err(self.invalid_escape(start, format!("unknown escape sequence '\\{}'", c))),
+        };
+        Ok(escaped)
+    }
+
+    // `\u{1F600}`: one to six hex digits naming a unicode scalar value
+    fn read_unicode_escape(&mut self, start: Span) -> Result<char, LexError> {
+        if self.peek(0) != Some('{') {
+            return Err(self.invalid_escape(start, "expected '{' after '\\u'"));
+        }
+        self.advance(1); // Skip '{'
+
+        let mut digits = String::new();
+        while let Some(c) = self.peek(0) {
+            if !c.is_ascii_hexdigit() {
+                break;
+            }
+            digits.push(c);
+            self.advance(1);
+        }
+
+        if self.peek(0) != Some('}') {
+            return Err(self.invalid_escape(start, "expected '}' to close the unicode escape"));
+        }
+        self.advance(1); // Skip '}'
+
+        if digits.is_empty() || digits.len() > 6 {
+            return Err(self.invalid_escape(start, "a unicode escape needs one to six hex digits"));
+        }
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.invalid_escape(start, format!("'{}' is not a valid unicode character", digits)))
+    }
+
+    fn invalid_escape(&self, start: Span, reason: impl Into<String>) -> LexError {
+        LexError {
+            kind: LexErrorKind::InvalidEscape(reason.into()),
+            span: Span { end: self.pos, ..start },
+        }
+    }
+
+    // Raw strings keep backslashes as they are: `r"\d+"`. Wrapping the quotes
+    // in hashes lets the string contain them: `r#"say "hi""#`.
+    fn read_raw_string(&mut self) -> Result<Token, LexError> {
+        let start = self.position();
+        self.advance(1); // Skip the 'r'
+
+        let mut hashes = 0;
+        while self.peek(0) == Some('#') {
+            hashes += 1;
+            self.advance(1);
+        }
+
+        let quote_char = match self.peek(0) {
+            Some(c @ ('"' | '\'')) => c,
+            _ => {
+                return Err(LexError {
+                    kind: LexErrorKind::UnexpectedCharacter('#'),
+                    span: Span { end: self.pos, ..start },
+                });
+            }
+        };
+        self.advance(1); // Skip the opening quote
+
+        let terminator = format!("{}{}", quote_char, "#".repeat(hashes));
+        let rest = self.rest();
+        match rest.find(&terminator) {
+            Some(length) => {
+                let content = &rest[..length];
+                self.advance(content.chars().count() + terminator.len());
+                Ok(self.token_from(start, content, TokenType::String))
+            }
+            None => {
+                while self.bump().is_some() {}
+                Err(LexError {
+                    kind: LexErrorKind::UnterminatedString,
+                    span: Span { end: self.pos, ..start },
+                })
+            }
+        }
     }
 
     fn read_binary_operator(&mut self, op: char) -> Token {
-        self.pos += 1; // Move past operator
-        Token { value: op.to_string(), token_type: TokenType::BinaryOperator }
+        let start = self.position();
+        self.advance(1); // Move past operator
+        self.token_from(start, &op.to_string(), TokenType::BinaryOperator)
     }
 
     fn read_comparison_operator(&mut self, op: char) -> Token {
-        self.pos += 1; // Move past operator
-        Token { value: op.to_string(), token_type: TokenType::BinaryOperator }
+        let start = self.position();
+        self.advance(1); // Move past operator
+        if self.peek(0) == Some('=') {
+            self.advance(1); // Move past '='
+            return self.token_from(start, &format!("{}=", op), TokenType::BinaryOperator);
+        }
+        self.token_from(start, &op.to_string(), TokenType::BinaryOperator)
+    }
+}
+
+// Letters, digits and `_`, plus combining marks so decomposed text such as
+// "e\u{301}" stays part of the identifier it belongs to
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || matches!(c,
+        '\u{0300}'..='\u{036F}' | '\u{1AB0}'..='\u{1AFF}' | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{20D0}'..='\u{20FF}' | '\u{FE20}'..='\u{FE2F}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(input: &str) -> Vec<Token> {
+        let mut lexer = Lexer::new(input);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next_token().expect("input should lex") {
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    fn lexer_error(input: &str) -> LexError {
+        let mut lexer = Lexer::new(input);
+        loop {
+            match lexer.next_token() {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("{:?} should not lex", input),
+                Err(error) => return error,
+            }
+        }
+    }
+
+    fn lex_error(input: &str) -> LexErrorKind {
+        lexer_error(input).kind
+    }
+
+    #[test]
+    fn number_literals() {
+        let values: Vec<String> = lex("3.14 1e-9 2.5E3 0xFF 0b101 0o17 1_000").into_iter().map(|t| t.value).collect();
+        assert_eq!(values, ["3.14", "1e-9", "2.5e3", "0xFF", "0b101", "0o17", "1000"]);
+    }
+
+    #[test]
+    fn number_followed_by_range_or_key() {
+        let types: Vec<TokenType> = lex("1..5").into_iter().map(|t| t.token_type).collect();
+        assert_eq!(types, [TokenType::Number, TokenType::DotDot, TokenType::Number]);
+        let types: Vec<TokenType> = lex("1.key").into_iter().map(|t| t.token_type).collect();
+        assert_eq!(types, [TokenType::Number, TokenType::Dot, TokenType::Identifier]);
+        let types: Vec<TokenType> = lex("o.show o?.new {if: 1}").into_iter().map(|t| t.token_type).collect();
+        assert_eq!(types, [
+            TokenType::Identifier, TokenType::Dot, TokenType::Identifier,
+            TokenType::Identifier, TokenType::QuestionDot, TokenType::Identifier,
+            TokenType::OpenBrace, TokenType::Identifier, TokenType::Colon, TokenType::Number, TokenType::CloseBrace,
+        ]);
+        assert!(matches!(lex_error("new"), LexErrorKind::ReservedWord(_)));
+    }
+
+    #[test]
+    fn malformed_numbers() {
+        for input in ["1.", "1. + 2", "1.e5", "0x", "1e", "12px", "1__0", "0b102", "1e400", "1.5e308_0"] {
+            assert!(matches!(lex_error(input), LexErrorKind::MalformedNumber(_)), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn errors_instead_of_panics() {
+        let mut lexer = Lexer::new("let x = @");
+        for _ in 0..3 {
+            lexer.next_token().unwrap();
+        }
+        let error = lexer.next_token().unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::UnexpectedCharacter('@'));
+        assert_eq!((error.span.start, error.span.end, error.span.line, error.span.column), (8, 9, 1, 9));
+        assert_eq!(lex_error("let class = 1"), LexErrorKind::ReservedWord("class".to_string()));
+        assert_eq!(lex_error("show \"abc"), LexErrorKind::UnterminatedString);
+        assert_eq!(Diagnostic::from(lexer_error("show \"abc")).code, "E0002");
+    }
+
+    #[test]
+    fn string_escapes() {
+        let values: Vec<String> = lex(r##""a\tb\n" 'it\'s' "\u{1F600}\\" r"raw\n" r#"q"t"#"##).into_iter().map(|t| t.value).collect();
+        assert_eq!(values, ["a\tb\n", "it's", "\u{1F600}\\", "raw\\n", "q\"t"]);
+        assert!(matches!(lex_error(r#""\q""#), LexErrorKind::InvalidEscape(_)));
+        assert!(matches!(lex_error(r#""\u{110000}""#), LexErrorKind::InvalidEscape(_)));
+    }
+
+    #[test]
+    fn template_tokens() {
+        let types: Vec<TokenType> = lex("`a ${b + 1} c`").into_iter().map(|t| t.token_type).collect();
+        assert_eq!(types, [
+            TokenType::TemplateStart,
+            TokenType::TemplateString,
+            TokenType::TemplateExprStart,
+            TokenType::Identifier,
+            TokenType::BinaryOperator,
+            TokenType::Number,
+            TokenType::TemplateExprEnd,
+            TokenType::TemplateString,
+            TokenType::TemplateEnd,
+        ]);
+        assert_eq!(lex_error("`open ${1}"), LexErrorKind::UnterminatedString);
+    }
+
+    fn spans(tokens: &[Token]) -> Vec<(&str, usize, usize, usize, usize)> {
+        tokens.iter().map(|t| (t.value.as_str(), t.span.start, t.span.end, t.span.line, t.span.column)).collect()
+    }
+
+    #[test]
+    fn unicode_identifiers_and_strings() {
+        let tokens = lex("let café = \"ü\"");
+        assert_eq!(spans(&tokens), [
+            ("let", 0, 3, 1, 1),
+            ("café", 4, 9, 1, 5),
+            ("=", 10, 11, 1, 10),
+            ("ü", 12, 16, 1, 12),
+        ]);
+        assert_eq!(tokens[1].token_type, TokenType::Identifier);
+        assert_eq!(tokens[3].token_type, TokenType::String);
+    }
+
+    #[test]
+    fn columns_count_characters_not_bytes() {
+        let tokens = lex("\"日本語🎉\" x\nlet y");
+        assert_eq!(spans(&tokens), [
+            ("日本語🎉", 0, 15, 1, 1),
+            ("x", 16, 17, 1, 8),
+            ("let", 18, 21, 2, 1),
+            ("y", 22, 23, 2, 5),
+        ]);
+    }
+
+    #[test]
+    fn combining_marks() {
+        let tokens = lex("\"e\\u{301}\" \"e\u{301}\" z");
+        assert_eq!(spans(&tokens), [
+            ("e\u{301}", 0, 10, 1, 1),
+            ("e\u{301}", 11, 16, 1, 12),
+            ("z", 17, 18, 1, 17),
+        ]);
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        let tokens = lex("/* outer /* inner */ still outer */ show\n/* a\nb */ x");
+        assert_eq!(spans(&tokens), [("show", 36, 40, 1, 37), ("x", 51, 52, 3, 6)]);
+        assert_eq!(lex_error("/* open /* inner */"), LexErrorKind::UnterminatedComment);
+    }
+
+    #[test]
+    fn doc_comments_are_kept() {
+        let tokens = lex("/// Adds.\n///   Indented\nfunc");
+        let docs: Vec<&str> = tokens[0].trivia.iter()
+            .map(|trivia| match trivia {
+                Trivia::DocComment { text, .. } => text.as_str(),
+            })
+            .collect();
+        assert_eq!(docs, ["Adds.", "  Indented"]);
+    }
+
+    #[test]
+    fn starting_at_a_later_line() {
+        let history = "func f() { }\nshow é\n";
+        let mut lexer = Lexer::starting_at(history, 13, 2);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next_token().unwrap() {
+            tokens.push(token);
+        }
+        assert_eq!(spans(&tokens), [("show", 13, 17, 2, 1), ("é", 18, 20, 2, 6)]);
+    }
+
+    #[test]
+    fn float_literal_range() {
+        assert_eq!(lex_error("1e400"), LexErrorKind::MalformedNumber("too large for a float".to_string()));
+        assert_eq!(lex("1e-400 1.7976931348623157e308 100000000000000000000000")[1].value, "1.7976931348623157e308");
+    }
+
+    #[test]
+    fn double_slash_after_an_operand_divides() {
+        let values = |input| lex(input).into_iter().map(|t| t.value).collect::<Vec<String>>();
+        assert_eq!(values("7 // 2"), ["7", "//", "2"]);
+        assert_eq!(values("(a) // b[0] // 2"), ["(", "a", ")", "//", "b", "[", "0", "]", "//", "2"]);
+        assert_eq!(values("show // a comment\nx"), ["show", "x"]);
+        assert_eq!(values("x; // a comment\n// another\ny"), ["x", "y"]);
+        assert_eq!(values("x\n// a comment\ny"), ["x", "y"]);
     }
 }
@@ -1,26 +1,65 @@
 mod ast;
+mod diagnostics;
+mod environment;
 mod interpreter;
 mod lexer;
+mod numbers;
 mod parser;
+mod span;
+mod strings;
 mod token_type;
 
+use std::env;
+use std::fs;
 use std::io::{self, Write};
+use std::process;
+use std::thread;
 use lexer::Lexer;
 use parser::Parser;
 use interpreter::Interpreter;
+use diagnostics::{Diagnostic, Severity};
 use std::error::Error;
 
+// Parsing and running scripts is recursive, nested calls and blocks need
+// more room than the default main thread stack gives. This is enough for
+// the interpreter's call depth limit and the parser's nesting limit even in
+// a debug build, only the part that is used is ever touched.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let worker = thread::Builder::new().stack_size(STACK_SIZE).spawn(start)?;
+    match worker.join() {
+        Ok(result) => result.map_err(|error| error as Box<dyn Error>),
+        Err(_) => process::exit(101), // The panic message has already been printed
+    }
+}
+
+fn start() -> Result<(), Box<dyn Error + Send + Sync>> {
+    // `KorvaqScrip script.kq` runs a file instead of starting the REPL
+    if let Some(path) = env::args().nth(1) {
+        let source = fs::read_to_string(&path)?;
+        let mut interpreter = Interpreter::new();
+        if !run(&mut interpreter, &path, &source, Lexer::new(&source)) {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
     println!("Welcome to KrovaqScrip v1.0.0");
     println!("type `.help` or `.license` for more information");
     let mut interpreter = Interpreter::new(); 
+    // Everything typed so far, one input per line. Functions from earlier
+    // inputs keep spans into it, so their errors show the line they came from.
+    let mut history = String::new();
 
     loop {
         print!(">> ");
         io::stdout().flush()?;
 
         let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
+        if io::stdin().read_line(&mut input)? == 0 {
+            break; // End of input
+        }
 
         let input = input.trim();
 
@@ -42,22 +81,11 @@ fn main() -> Result<(), Box<dyn Error>> {
             continue;
         }
 
-        let lexer = Lexer::new(input);
-        let mut parser = Parser::new(lexer);
-
-        // Parse the input and handle errors
-        let ast = match parser.parse() {
-            Ok(ast) => ast,
-            Err(e) => {
-                eprintln!("Parsing error: {}", e);
-                continue;
-            }
-        };
-
-        // Use the existing interpreter instance to interpret the AST
-        if let Err(e) = interpreter.interpret(ast) {
-            eprintln!("Interpretation error: {}", e);
-        }
+        let start = history.len();
+        let line = history.lines().count() + 1;
+        history.push_str(input);
+        history.push('\n');
+        run(&mut interpreter, "<repl>", &history, Lexer::starting_at(&history, start, line));
 
         print!("");
     }
@@ -65,6 +93,29 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Parse and run what `lexer` reads from `source`, printing every diagnostic.
+// Returns false if anything went wrong.
+fn run(interpreter: &mut Interpreter, file_name: &str, source: &str, lexer: Lexer) -> bool {
+    let mut parser = Parser::new(lexer);
+
+    // Report every parse error at once and don't run a broken program
+    let (ast, diagnostics) = parser.parse();
+    for d in &diagnostics {
+        eprint!("{}", d.render(file_name, source));
+    }
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        return false;
+    }
+
+    // Use the existing interpreter instance to interpret the AST
+    if let Err(e) = interpreter.interpret(ast) {
+        eprint!("{}", Diagnostic::from(e).render(file_name, source));
+        return false;
+    }
+
+    true
+}
+
 fn print_license() {
     println!("-----------------------------------------------------------------");
     println!("KORVAQ LICENSE");
@@ -112,6 +163,8 @@ fn print_help() {
     println!("USAGE:");
     println!("Type KorvaqScrip commands and expressions directly at the prompt.");
     println!("The interpreter will evaluate and display the result if valid.");
+    println!("Run `KorvaqScrip <file.kq>` to run a whole script instead, every");
+    println!("syntax error in it is reported before anything runs.");
     println!();
     println!("SYNTAX HIGHLIGHTS:");
     println!("let <var> = <value>     - Define a mutable variable.");
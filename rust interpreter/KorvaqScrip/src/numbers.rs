@@ -0,0 +1,232 @@
+use crate::interpreter::{RuntimeError, Value};
+use crate::span::Span;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{FromPrimitive, Pow, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+
+// Numbers come in three kinds. Integers are `Int` while they fit in 64 bits
+// and become `BigInt` once they don't, so they never lose precision. A
+// `BigInt` is always too big for an `Int`, every integer result goes through
+// `integer` to keep it that way. `Float` is a 64-bit floating point number.
+//
+// Promotion rules for `+ - * % ** //`:
+//   integer  op  integer  integer, exact however large it gets
+//   integer  op  float    float
+//   float    op  float    float
+// `/` always gives a float, `//` divides and rounds down, `7 // 2` is 3.
+// `**` with a negative exponent gives a float. `^` only takes integers.
+
+// Largest result `**` will build, in bits, so a typo can't eat all the memory
+const MAX_POWER_BITS: u64 = 1 << 24;
+
+// The integer `n`, as an `Int` when it fits
+pub fn integer(n: BigInt) -> Value {
+    match n.to_i64() {
+        Some(n) => Value::Int(n),
+        None => Value::BigInt(n),
+    }
+}
+
+pub fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Int(_) | Value::BigInt(_) | Value::Float(_))
+}
+
+// Any number as a float, big integers are rounded
+pub fn to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(*n as f64),
+        Value::BigInt(n) => n.to_f64(),
+        Value::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn to_bigint(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Int(n) => Some(BigInt::from(*n)),
+        Value::BigInt(n) => Some(n.clone()),
+        _ => None,
+    }
+}
+
+// The number written in `text`, ignoring spaces around it
+pub fn parse(text: &str) -> Option<Value> {
+    let text = text.trim();
+    match text.parse::<BigInt>() {
+        Ok(n) => Some(integer(n)),
+        Err(_) => text.parse::<f64>().ok().filter(|n| n.is_finite()).map(Value::Float),
+    }
+}
+
+// Compare two numbers exactly, an integer and a float are compared by value
+// without rounding the integer. `None` when either is NaN or not a number.
+pub fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Float(_), _) => compare(right, left).map(Ordering::reverse),
+        (_, Value::Float(b)) if b.is_nan() => None,
+        (_, Value::Float(b)) if b.is_infinite() => Some(if *b > 0.0 { Ordering::Less } else { Ordering::Greater }),
+        (_, Value::Float(b)) => {
+            let whole = BigInt::from_f64(b.floor())?;
+            match to_bigint(left)?.cmp(&whole) {
+                // The float has a fraction on top of `whole`, so it is bigger
+                Ordering::Equal if b.fract() != 0.0 => Some(Ordering::Less),
+                ordering => Some(ordering),
+            }
+        }
+        _ => Some(to_bigint(left)?.cmp(&to_bigint(right)?)),
+    }
+}
+
+// `1 == 1.0`, integers and floats with the same value are equal
+pub fn equals(left: &Value, right: &Value) -> bool {
+    compare(left, right) == Some(Ordering::Equal)
+}
+
+// `-n`
+pub fn negate(value: Value) -> Value {
+    match value {
+        Value::Int(n) => match n.checked_neg() {
+            Some(n) => Value::Int(n),
+            None => integer(-BigInt::from(n)),
+        },
+        Value::BigInt(n) => integer(-n),
+        Value::Float(n) => Value::Float(-n),
+        other => other,
+    }
+}
+
+// `left operator right` for two numbers
+pub fn binary(operator: &str, left: &Value, right: &Value, span: Span) -> Result<Value, RuntimeError> {
+    let ordering = || compare(left, right);
+    match operator {
+        "<" => return Ok(Value::Boolean(ordering() == Some(Ordering::Less))),
+        ">" => return Ok(Value::Boolean(ordering() == Some(Ordering::Greater))),
+        "<=" => return Ok(Value::Boolean(matches!(ordering(), Some(Ordering::Less | Ordering::Equal)))),
+        ">=" => return Ok(Value::Boolean(matches!(ordering(), Some(Ordering::Greater | Ordering::Equal)))),
+        "+" | "-" | "*" | "/" | "%" | "//" | "**" | "^" => {}
+        _ => return Err(RuntimeError::new(format!("Unsupported operator: {}", operator), span).with_kind("TypeError")),
+    }
+
+    if matches!(operator, "/" | "%" | "//") && to_f64(right) == Some(0.0) {
+        return Err(RuntimeError::new("Division by zero", span).with_kind("ZeroDivisionError"));
+    }
+
+    match (left, right) {
+        (Value::Float(_), _) | (_, Value::Float(_)) if operator == "^" => Err(RuntimeError::new(
+            format!("Bitwise XOR needs integers, found {} and {}", left, right),
+            span,
+        ).with_kind("TypeError")),
+        (Value::Float(_), _) | (_, Value::Float(_)) => float_operation(operator, left, right),
+        _ if operator == "/" => float_operation(operator, left, right),
+        _ if operator == "**" => integer_power(left, right, span),
+        _ => Ok(integer_operation(operator, left, right)),
+    }
+}
+
+fn float_operation(operator: &str, left: &Value, right: &Value) -> Result<Value, RuntimeError> {
+    let (a, b) = (to_f64(left).unwrap_or(f64::NAN), to_f64(right).unwrap_or(f64::NAN));
+    Ok(Value::Float(match operator {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" => a / b,
+        "%" => a % b,
+        "//" => (a / b).floor(),
+        _ => a.powf(b),
+    }))
+}
+
+fn integer_operation(operator: &str, left: &Value, right: &Value) -> Value {
+    if let (Value::Int(a), Value::Int(b)) = (left, right) {
+        let result = match operator {
+            "+" => a.checked_add(*b),
+            "-" => a.checked_sub(*b),
+            "*" => a.checked_mul(*b),
+            "%" => a.checked_rem(*b),
+            // `i64::MIN // -1` is the one division that overflows
+            "//" if *b == -1 => a.checked_neg(),
+            "//" => Some(a.div_floor(b)),
+            _ => Some(a ^ b),
+        };
+        if let Some(n) = result {
+            return Value::Int(n);
+        }
+    }
+
+    // Too big for 64 bits, work it out exactly
+    let a = to_bigint(left).unwrap_or_default();
+    let b = to_bigint(right).unwrap_or_default();
+    integer(match operator {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "%" => a % b,
+        "//" => a.div_floor(&b),
+        _ => a ^ b,
+    })
+}
+
+fn integer_power(base: &Value, exponent: &Value, span: Span) -> Result<Value, RuntimeError> {
+    let base = to_bigint(base).unwrap_or_default();
+    let exponent = to_bigint(exponent).unwrap_or_default();
+
+    // `2 ** -1` is a fraction, so it can only be a float
+    if exponent.is_negative() {
+        let result = base.to_f64().unwrap_or(f64::NAN).powf(exponent.to_f64().unwrap_or(f64::NAN));
+        return Ok(Value::Float(result));
+    }
+    // 0, 1 and -1 never grow, only whether the exponent is zero, odd or even matters
+    if base.bits() <= 1 {
+        let exponent: u32 = if exponent.is_zero() { 0 } else if exponent.is_odd() { 1 } else { 2 };
+        return Ok(integer(Pow::pow(&base, exponent)));
+    }
+
+    let too_large = || RuntimeError::new(format!("The result of {} ** {} is too large", base, exponent), span).with_kind("ValueError");
+    let exponent = exponent.to_u64().ok_or_else(too_large)?;
+    if base.bits().saturating_mul(exponent) > MAX_POWER_BITS {
+        return Err(too_large());
+    }
+    Ok(integer(Pow::pow(&base, exponent)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::tests::{error_message, evaluate};
+
+    #[test]
+    fn integer_and_float_arithmetic() {
+        let cases: &[(&str, &str)] = &[
+            ("10 / 4", "2.5"),
+            ("10 / 5", "2.0"),
+            ("7 // 2", "3"),
+            ("-7 // 2", "-4"),
+            ("7.5 // 2", "3.0"),
+            ("1 + 0.5", "1.5"),
+            ("2 ** 100", "1267650600228229401496703205376"),
+            ("2 ** -1", "0.5"),
+            ("9223372036854775807 + 1", "9223372036854775808"),
+            ("(-9223372036854775807 - 1) // -1", "9223372036854775808"),
+            ("5 ^ 3", "6"),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(evaluate(expression).unwrap().to_string(), *expected, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn exact_comparison_of_integers_and_floats() {
+        assert_eq!(evaluate("9007199254740993 > 9007199254740992.0").unwrap().to_string(), "true");
+        assert_eq!(evaluate("1 == 1.0").unwrap().to_string(), "true");
+        assert_eq!(evaluate("1 === 1.0").unwrap().to_string(), "false");
+    }
+
+    #[test]
+    fn number_errors() {
+        assert_eq!(error_message("1 // 0", "ZeroDivisionError"), "Division by zero");
+        assert_eq!(error_message("1.5 ^ 1", "TypeError"), "Bitwise XOR needs integers, found 1.5 and 1");
+        assert_eq!(error_message("2 ** 100000000", "ValueError"), "The result of 2 ** 100000000 is too large");
+    }
+}
@@ -1,238 +1,607 @@
 use crate::ast::ASTNode;
+use crate::diagnostics::Diagnostic;
+use crate::span::Span;
 use crate::token_type::TokenType;
-use crate::lexer::{Lexer, Token};
+use crate::lexer::{Lexer, Token, Trivia};
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+// Code used for every syntax error reported by the parser
+const SYNTAX_ERROR: &str = "E0100";
+
+// Prefix operators sit between `* / %` and `**` in `get_precedence`
+const UNARY_PRECEDENCE: i32 = 9;
+
+// How deeply expressions and blocks may nest before parsing gives up, well
+// within what the recursive parser and interpreter can handle on their stack
+const MAX_NESTING: usize = 200;
 
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     current_token: Option<Token>,
+    previous_span: Option<Span>, // Span of the last token that was consumed
+    errors: Vec<Diagnostic>,     // Every error found so far, parsing carries on after each one
+    loop_depth: usize,           // How many loops enclose the current statement, inside the current function
+    function_depth: usize,       // How many function bodies enclose the current statement
+    brace_depth: usize,          // Number of '{' consumed so far minus the '}' consumed
+    nesting: usize,              // How many expressions and blocks enclose the one being parsed
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(mut lexer: Lexer<'a>) -> Self {
-        let current_token = lexer.next_token();
+    pub fn new(lexer: Lexer<'a>) -> Self {
         Parser {
             lexer,
-            current_token,
+            current_token: None,
+            previous_span: None,
+            errors: Vec::new(),
+            loop_depth: 0,
+            function_depth: 0,
+            brace_depth: 0,
+            nesting: 0,
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<ASTNode>, String> {
+    // Parse the whole input. Statements that fail to parse show up as
+    // `ASTNode::Error` in the returned tree and their diagnostics are
+    // returned alongside it, so the caller decides whether to go on.
+    pub fn parse(&mut self) -> (Vec<ASTNode>, Vec<Diagnostic>) {
+        self.advance_recovering(); // Load the first token
+        let statements = self.parse_statements(false);
+        (statements, std::mem::take(&mut self.errors))
+    }
+
+    // Parse statements until the input runs out, or until the closing '}'
+    // when `in_block` is set
+    fn parse_statements(&mut self, in_block: bool) -> Vec<ASTNode> {
         let mut statements = Vec::new();
 
         while let Some(ref token) = self.current_token {
-            match token.token_type {
-                TokenType::Let => {
-                    statements.push(self.parse_variable_declaration(false)?);
-                }
-                TokenType::Make => {
-                    statements.push(self.parse_variable_declaration(true)?);
-                }
-                TokenType::Show => {
-                    statements.push(self.parse_show_statement()?);
-                }
-                TokenType::DelVar => {
-                    statements.push(self.parse_delvar_statement()?);
-                }
-                TokenType::UpperCase => {
-                    statements.push(self.parse_to_uppercase_statement()?);
-                }
-                TokenType::LowerCase => {
-                    statements.push(self.parse_to_lowercase_statement()?);
-                }
-                TokenType::Identifier => {
-                    statements.push(self.parse_statement_or_identifier()?);
+            if in_block && token.token_type == TokenType::CloseBrace {
+                break;
+            }
+            let start = token.span;
+            let depth = self.brace_depth;
+            match self.parse_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => {
+                    self.errors.push(error);
+                    let span = self.synchronize(start, depth);
+                    statements.push(ASTNode::Error { span });
                 }
-                TokenType::BooleanLiteral => {
-                    let bool_value = match token.value.as_str() {
-                        "true" => true,
-                        "false" => false,
-                        _ => return Err("Invalid boolean literal".to_string()),
-                    };
-                    statements.push(ASTNode::ValueBool { value: bool_value });
+            }
+        }
+
+        statements
+    }
+
+    fn parse_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let token_type = match self.current_token {
+            Some(ref token) => token.token_type.clone(),
+            None => return Err(self.error("Unexpected end of input")),
+        };
+
+        // Only declarations can be documented
+        if !matches!(token_type, TokenType::Let | TokenType::Make | TokenType::Func) {
+            self.warn_detached_docs();
+        }
+
+        match token_type {
+            TokenType::Let => self.parse_variable_declaration(false),
+            TokenType::Make => self.parse_variable_declaration(true),
+            TokenType::Show => self.parse_show_statement(),
+            TokenType::DelVar => self.parse_delvar_statement(),
+            TokenType::DelFunc => self.parse_delfunc_statement(),
+            TokenType::ArrAdd | TokenType::ArrDel => self.parse_array_change(),
+            TokenType::DelKey => self.parse_delkey_statement(),
+            TokenType::If => self.parse_if_statement(),
+            TokenType::While => self.parse_while_statement(),
+            TokenType::Loop => self.parse_counted_loop(),
+            TokenType::For => self.parse_for_in_loop(),
+            TokenType::Func => self.parse_function_declaration(),
+            TokenType::Return => self.parse_return_statement(),
+            TokenType::Call | TokenType::Arr => self.parse_expression(0),
+            TokenType::Break | TokenType::Continue => self.parse_loop_control(),
+            TokenType::Try => self.parse_try_statement(),
+            TokenType::Throw => self.parse_throw_statement(),
+            TokenType::UpperCase | TokenType::LowerCase => self.parse_expression(0),
+            TokenType::Identifier => self.parse_statement_or_identifier(),
+            TokenType::BooleanLiteral => self.parse_primary(),
+            _ => Err(self.error(format!("Unexpected token: {:?}", token_type))),
+        }
+    }
+
+    // Panic-mode recovery: throw tokens away until one that can start a new
+    // statement, so a single mistake doesn't hide the ones after it.
+    // Braces opened by the failed statement are skipped along with it, the
+    // '}' of an enclosing block is left for that block. `depth` is the brace
+    // depth the statement started at.
+    // Returns the span of the code that was skipped.
+    fn synchronize(&mut self, start: Span, depth: usize) -> Span {
+        let mut end = start;
+
+        // Always make progress, otherwise the same token fails again
+        let mut must_skip = self.current_span().start == start.start;
+
+        while let Some(ref token) = self.current_token {
+            if !must_skip && self.brace_depth <= depth {
+                match token.token_type {
+                    TokenType::Let | TokenType::Make | TokenType::Show | TokenType::If | TokenType::While
+                    | TokenType::Loop | TokenType::For | TokenType::Func | TokenType::Return
+                    | TokenType::Try | TokenType::Throw | TokenType::CloseBrace => break,
+                    _ => {}
                 }
-                _ => return Err(format!("Unexpected token: {:?}", token.token_type)),
             }
+            must_skip = false;
+            end = token.span;
+            self.advance_recovering();
+        }
 
-            self.next_token(); // Move to the next token after processing each statement
+        start.to(end)
+    }
+
+    // Parse `if cond { ... }` followed by any number of `else if` and an optional `else`.
+    // Parentheses around the condition are just a grouped expression.
+    fn parse_if_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.next_token()?; // Move past 'if'
+
+        let condition = self.parse_expression(0)?;
+        let consequent = self.parse_block("if condition")?;
+        let mut end = consequent.span();
+
+        let alternative = if let Some(Token { token_type: TokenType::Else, .. }) = self.current_token {
+            self.next_token()?; // Move past 'else'
+            let alternative = if let Some(Token { token_type: TokenType::If, .. }) = self.current_token {
+                self.parse_if_statement()? // `else if` chains nest as the alternative
+            } else {
+                self.parse_block("'else'")?
+            };
+            end = alternative.span();
+            Some(Box::new(alternative))
+        } else {
+            None
+        };
+
+        Ok(ASTNode::IfStatement {
+            condition: Box::new(condition),
+            consequent: Box::new(consequent),
+            alternative,
+            span: start.to(end),
+        })
+    }
+
+    fn parse_while_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.next_token()?; // Move past 'while'
+
+        let condition = self.parse_expression(0)?;
+        let body = self.parse_loop_body("while condition")?;
+
+        Ok(ASTNode::WhileLoop {
+            span: start.to(body.span()),
+            condition: Box::new(condition),
+            body: Box::new(body),
+        })
+    }
+
+    // Parse `loop (i, start, end)` or `loop (i, start, end, step)` followed by a block
+    fn parse_counted_loop(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start_span = self.current_span();
+        self.next_token()?; // Move past 'loop'
+
+        let open_span = self.expect(TokenType::OpenParen, "'(' after 'loop'")?;
+        let variable = self.expect_identifier("loop variable name")?;
+        self.expect(TokenType::Comma, "',' after the loop variable")?;
+        let start = self.parse_expression(0)?;
+        self.expect(TokenType::Comma, "',' after the start value")?;
+        let end = self.parse_expression(0)?;
+
+        let step = if let Some(Token { token_type: TokenType::Comma, .. }) = self.current_token {
+            self.next_token()?; // Skip ','
+            Some(Box::new(self.parse_expression(0)?))
+        } else {
+            None
+        };
+
+        if let Some(Token { token_type: TokenType::CloseParen, .. }) = self.current_token {
+            self.next_token()?; // Skip ')'
+        } else {
+            return Err(self.error("Expected ')' after the loop bounds")
+                .with_label("expected ')'")
+                .with_secondary(open_span, "unclosed '(' opened here")
+                .with_help("write `loop (i, start, end)` or `loop (i, start, end, step)`"));
         }
 
-        Ok(statements)
+        let body = self.parse_loop_body("loop bounds")?;
+
+        Ok(ASTNode::CountedLoop {
+            variable,
+            start: Box::new(start),
+            end: Box::new(end),
+            step,
+            span: start_span.to(body.span()),
+            body: Box::new(body),
+        })
     }
 
-    fn parse_to_uppercase_statement(&mut self) -> Result<ASTNode, String> {
-        // Check if the next token is an opening parenthesis
-        if let Some(Token { token_type: TokenType::OpenParen, .. }) = self.current_token {
-            self.next_token(); // Skip '('
-    
-            // Parse the expression inside the parentheses (it could be a variable or string literal)
-            let expr = self.parse_expression(0)?;
-    
-            // Ensure we have a closing parenthesis
-            if let Some(Token { token_type: TokenType::CloseParen, .. }) = self.current_token {
-                self.next_token(); // Skip ')'
+    // Parse `for x in iterable { ... }`, where the iterable may be a range `start..end`
+    fn parse_for_in_loop(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start_span = self.current_span();
+        self.next_token()?; // Move past 'for'
+
+        let variable = self.expect_identifier("loop variable name after 'for'")?;
+        self.expect(TokenType::In, "'in' after the loop variable")?;
+
+        let mut iterable = self.parse_expression(0)?;
+        if let Some(Token { token_type: TokenType::DotDot, .. }) = self.current_token {
+            self.next_token()?; // Skip '..'
+            let end = self.parse_expression(0)?;
+            iterable = ASTNode::Range {
+                span: iterable.span().to(end.span()),
+                start: Box::new(iterable),
+                end: Box::new(end),
+            };
+        }
+
+        let body = self.parse_loop_body("for loop iterable")?;
+
+        Ok(ASTNode::ForInLoop {
+            variable,
+            iterable: Box::new(iterable),
+            span: start_span.to(body.span()),
+            body: Box::new(body),
+        })
+    }
+
+    // Parse `func name(a, b) { ... }`
+    fn parse_function_declaration(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        let doc = self.current_doc();
+        self.next_token()?; // Move past 'func'
+
+        let name = self.expect_identifier("function name after 'func'")?;
+        let (params, body) = self.parse_function_rest("'(' after the function name")?;
+
+        Ok(ASTNode::FunctionDeclaration {
+            name,
+            params,
+            doc,
+            span: start.to(body.span()),
+            body: Box::new(body),
+        })
+    }
+
+    // Parse an anonymous function, `func(a, b) { ... }`, used as a value
+    fn parse_function_expression(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.next_token()?; // Move past 'func'
+
+        let (params, body) = self.parse_function_rest("'(' after 'func'")?;
+
+        Ok(ASTNode::FunctionExpression {
+            name: None,
+            params,
+            span: start.to(body.span()),
+            body: Box::new(body),
+        })
+    }
+
+    // `add = func(a, b) { ... }`, with or without `let`, names the function `add`
+    fn name_function(value: &mut ASTNode, variable: &str) {
+        if let ASTNode::FunctionExpression { ref mut name, .. } = value {
+            *name = Some(variable.to_string());
+        }
+    }
+
+    // Parse the `(params) { body }` shared by declared and anonymous functions
+    fn parse_function_rest(&mut self, expected_paren: &str) -> Result<(Vec<String>, ASTNode), Diagnostic> {
+        let open_span = self.expect(TokenType::OpenParen, expected_paren)?;
+
+        let mut params: Vec<String> = Vec::new();
+        while let Some(Token { token_type: TokenType::Identifier, value, span, .. }) = self.current_token.clone() {
+            if params.contains(&value) {
+                return Err(Diagnostic::error(SYNTAX_ERROR, format!("Duplicate parameter '{}'", value), span)
+                    .with_label("used more than once"));
+            }
+            params.push(value);
+            self.next_token()?; // Move past the parameter
+            if let Some(Token { token_type: TokenType::Comma, .. }) = self.current_token {
+                self.next_token()?; // Skip ','
             } else {
-                return Err("Expected ')' after uppercase expression".to_string());
+                break;
             }
-    
-            // Return an ASTNode for the uppercase operation
-            Ok(ASTNode::Uppercase { expr: Box::new(expr) })
+        }
+
+        if let Some(Token { token_type: TokenType::CloseParen, .. }) = self.current_token {
+            self.next_token()?; // Skip ')'
         } else {
-            Err("Expected '(' after 'uppercase'".to_string())
-        }
-    }
-    
-
-    fn parse_to_lowercase_statement(&mut self) -> Result<ASTNode, String> {
-        // Check if the next token is an opening parenthesis
-        if let Some(Token { token_type: TokenType::OpenParen, .. }) = self.current_token {
-            self.next_token(); // Skip '('
-    
-            // Parse the expression inside the parentheses (it could be a variable or string literal)
-            let expr = self.parse_expression(0)?;
-    
-            // Ensure we have a closing parenthesis
-            if let Some(Token { token_type: TokenType::CloseParen, .. }) = self.current_token {
-                self.next_token(); // Skip ')'
-            } else {
-                return Err("Expected ')' after lowercase expression".to_string());
+            return Err(self.error("Expected ')' after the parameters")
+                .with_label("expected ')'")
+                .with_secondary(open_span, "unclosed '(' opened here"));
+        }
+
+        // `break` and `continue` can't reach loops outside the function
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        self.function_depth += 1;
+        let body = self.parse_block("the parameters");
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+
+        Ok((params, body?))
+    }
+
+    // Parse `return` or `return value`. The value is left out when the
+    // function body ends right after the keyword.
+    fn parse_return_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        if self.function_depth == 0 {
+            return Err(self.error("'return' outside of a function")
+                .with_label("cannot 'return' here"));
+        }
+        self.next_token()?; // Move past 'return'
+
+        match self.current_token {
+            None | Some(Token { token_type: TokenType::CloseBrace, .. }) => {
+                Ok(ASTNode::Return { value: None, span: start })
             }
-    
-            // Return an ASTNode for the lowercase operation
-            Ok(ASTNode::Lowercase { expr: Box::new(expr) })
+            _ => {
+                let value = self.parse_expression(0)?;
+                Ok(ASTNode::Return { span: start.to(value.span()), value: Some(Box::new(value)) })
+            }
+        }
+    }
+
+    // Parse `(a, b, ...)` after the name of a called function
+    fn parse_call_arguments(&mut self, callee: ASTNode, optional: bool) -> Result<ASTNode, Diagnostic> {
+        let open_span = self.expect(TokenType::OpenParen, "'(' after the function name")?;
+
+        let mut arguments = Vec::new();
+        if let Some(Token { token_type: TokenType::CloseParen, .. }) = self.current_token {
+            // No arguments
+        } else {
+            loop {
+                arguments.push(self.parse_expression(0)?);
+                if let Some(Token { token_type: TokenType::Comma, .. }) = self.current_token {
+                    self.next_token()?; // Skip ','
+                } else {
+                    break;
+                }
+            }
+        }
+
+        if let Some(Token { token_type: TokenType::CloseParen, span, .. }) = self.current_token {
+            self.next_token()?; // Skip ')'
+            Ok(ASTNode::Call { span: callee.span().to(span), callee: Box::new(callee), arguments, optional })
         } else {
-            Err("Expected '(' after 'lowercase'".to_string())
+            Err(self.error("Expected ')' after the arguments")
+                .with_label("expected ')'")
+                .with_secondary(open_span, "unclosed '(' opened here"))
         }
     }
-    
-    fn parse_block(&mut self) -> Result<Vec<ASTNode>, String> {
-        let mut statements = Vec::new();
-        
-        // While there are more tokens and we don't hit the closing brace
-        while let Some(ref token) = self.current_token {
-            if token.token_type == TokenType::CloseBrace {
-                break; // Stop if we encounter a closing brace
-            }
-            
-            // Parse the next statement and extend it to the statements vector
-            statements.extend(self.parse()?); // Flatten the structure using `extend`
-    
-            // Move to the next token
-            self.next_token();
-        }
-    
-        Ok(statements)
-    }    
-
-    fn parse_delvar_statement(&mut self) -> Result<ASTNode, String> {
-        self.next_token(); // Move to the identifier
-    
+
+    // Parse the block of a loop, where `break` and `continue` are allowed
+    fn parse_loop_body(&mut self, after: &str) -> Result<ASTNode, Diagnostic> {
+        self.loop_depth += 1;
+        let body = self.parse_block(after);
+        self.loop_depth -= 1;
+        body
+    }
+
+    fn parse_loop_control(&mut self) -> Result<ASTNode, Diagnostic> {
+        let token = self.current_token.clone().expect("called on 'break' or 'continue'");
+        if self.loop_depth == 0 {
+            return Err(self.error(format!("'{}' outside of a loop", token.value))
+                .with_label(format!("cannot '{}' here", token.value)));
+        }
+        self.next_token()?; // Move past the keyword
+
+        Ok(match token.token_type {
+            TokenType::Break => ASTNode::Break { span: token.span },
+            _ => ASTNode::Continue { span: token.span },
+        })
+    }
+
+    // Parse `{ statements }`. `after` names what the block follows, for the error message.
+    fn parse_block(&mut self, after: &str) -> Result<ASTNode, Diagnostic> {
+        let open_span = match self.current_token {
+            Some(Token { token_type: TokenType::OpenBrace, span, .. }) => span,
+            _ => {
+                return Err(self.error(format!("Expected '{{' after {}", after))
+                    .with_label("expected '{'"));
+            }
+        };
+        self.next_token()?; // Skip '{'
+
+        let statements = self.nested(|parser| Ok(parser.parse_statements(true)))?;
+
+        if let Some(Token { token_type: TokenType::CloseBrace, span, .. }) = self.current_token {
+            self.next_token()?; // Skip '}'
+            Ok(ASTNode::Block { statements, span: open_span.to(span) })
+        } else {
+            Err(self.error("Expected '}' to close the block")
+                .with_label("expected '}'")
+                .with_secondary(open_span, "block opened here"))
+        }
+    }
+
+    // Parse `try { ... }` followed by `catch (name) { ... }`, `finally { ... }`
+    // or both. `catch { ... }` leaves out the name when the error isn't needed.
+    fn parse_try_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.next_token()?; // Move past 'try'
+
+        let body = self.parse_block("'try'")?;
+        let mut end = body.span();
+
+        let mut error_name = None;
+        let mut handler = None;
+        if let Some(Token { token_type: TokenType::Catch, .. }) = self.current_token {
+            self.next_token()?; // Move past 'catch'
+            if let Some(Token { token_type: TokenType::OpenParen, .. }) = self.current_token {
+                self.next_token()?; // Skip '('
+                error_name = Some(self.expect_identifier("the error variable name after 'catch ('")?);
+                self.expect(TokenType::CloseParen, "')' after the error variable name")?;
+            }
+            let block = self.parse_block("'catch'")?;
+            end = block.span();
+            handler = Some(Box::new(block));
+        }
+
+        let mut finally = None;
+        if let Some(Token { token_type: TokenType::Finally, .. }) = self.current_token {
+            self.next_token()?; // Move past 'finally'
+            let block = self.parse_block("'finally'")?;
+            end = block.span();
+            finally = Some(Box::new(block));
+        }
+
+        if handler.is_none() && finally.is_none() {
+            return Err(self.error("Expected 'catch' or 'finally' after the try block")
+                .with_help("write `catch (error) { ... }` to handle the error"));
+        }
+
+        Ok(ASTNode::TryStatement { body: Box::new(body), error_name, handler, finally, span: start.to(end) })
+    }
+
+    // Parse `throw value`
+    fn parse_throw_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.next_token()?; // Move past 'throw'
+
+        let value = self.parse_expression(0)?;
+        Ok(ASTNode::Throw { span: start.to(value.span()), value: Box::new(value) })
+    }
+
+    fn parse_delvar_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.next_token()?; // Move to the identifier
+
         if let Some(ref token) = self.current_token {
             match token.token_type {
                 TokenType::Identifier => {
                     let name = token.value.clone(); // Clone the value
-                    self.next_token(); // Move past the identifier
-                    Ok(ASTNode::DelVar { name })
+                    let span = start.to(token.span);
+                    self.next_token()?; // Move past the identifier
+                    Ok(ASTNode::DelVar { name, span })
                 }
-                _ => Err("Expected identifier after 'delvar'".to_string()),
+                _ => Err(self.error("Expected identifier after 'delvar'")),
             }
         } else {
-            Err("Expected identifier after 'delvar'".to_string())
+            Err(self.error("Expected identifier after 'delvar'"))
         }
     }
 
-    fn parse_variable_declaration(&mut self, is_constant: bool) -> Result<ASTNode, String> {
-        self.next_token(); // Move past 'let' or 'make'
+    fn parse_delfunc_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.next_token()?; // Move to the function name
 
-        let var_name = if let Some(ref token) = self.current_token {
-            if token.token_type == TokenType::Identifier {
-                token.value.clone()
-            } else {
-                return Err("Expected variable name after 'let' or 'make'".to_string());
+        match self.current_token {
+            Some(Token { token_type: TokenType::Identifier, ref value, span, .. }) => {
+                let name = value.clone();
+                self.next_token()?; // Move past the function name
+                Ok(ASTNode::DelFunc { name, span: start.to(span) })
             }
-        } else {
-            return Err("Expected variable name after 'let' or 'make'".to_string());
-        };
+            _ => Err(self.error("Expected function name after 'delfunc'")
+                .with_help("write `delfunc name`, or `delfunc all` to delete every function")),
+        }
+    }
 
-        self.next_token(); // Move to the next token
+    fn parse_variable_declaration(&mut self, is_constant: bool) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        let doc = self.current_doc();
+        self.next_token()?; // Move past 'let' or 'make'
+
+        let var_name = self.expect_identifier("variable name after 'let' or 'make'")?;
 
         if let Some(ref token) = self.current_token {
             if token.token_type == TokenType::Equals {
-                self.next_token(); // Move past '='
-                let value_node = self.parse_expression(0)?;
+                self.next_token()?; // Move past '='
+                let mut value_node = self.parse_expression(0)?;
+                Self::name_function(&mut value_node, &var_name);
                 return Ok(ASTNode::VariableDeclaration {
                     name: var_name,
                     is_constant,
+                    doc,
+                    span: start.to(value_node.span()),
                     value: Box::new(value_node),
                 });
             } else {
-                return Err("Expected '=' after variable name".to_string());
+                return Err(self.error("Expected '=' after variable name"));
             }
         }
 
-        Err("Invalid variable declaration".to_string())
+        Err(self.error("Invalid variable declaration"))
     }
 
-    fn parse_statement_or_identifier(&mut self) -> Result<ASTNode, String> {
-        if let Some(Token { value: var_name, token_type }) = &self.current_token {
+    fn parse_statement_or_identifier(&mut self) -> Result<ASTNode, Diagnostic> {
+        if let Some(Token { value: var_name, token_type, span, .. }) = &self.current_token {
             if *token_type == TokenType::Identifier {
                 let var_name = var_name.clone();
-                self.next_token(); // Move past the identifier
+                let start = *span;
+                self.next_token()?; // Move past the identifier
+
+                // Calls and indexing, `f(1)` or `a[0]`
+                let target = self.parse_postfix(ASTNode::Variable { name: var_name.clone(), span: start })?;
 
                 // Check if the next token is an `=` for assignment
                 if let Some(Token { token_type: TokenType::Equals, .. }) = self.current_token {
-                    self.next_token(); // Move past `=`
-                    let value_node = self.parse_expression(0)?; // Parse the right-hand side expression
-                    return Ok(ASTNode::VariableDeclaration {
-                        name: var_name,
-                        is_constant: false, // Treat it as mutable if using `x = 1` form
-                        value: Box::new(value_node),
-                    });
+                    self.next_token()?; // Move past `=`
+                    let mut value_node = self.parse_expression(0)?; // Parse the right-hand side expression
+                    if let ASTNode::Variable { ref name, .. } = target {
+                        Self::name_function(&mut value_node, name);
+                    }
+                    let span = start.to(value_node.span());
+                    let value = Box::new(value_node);
+                    return match target {
+                        ASTNode::Variable { name, .. } => Ok(ASTNode::Assignment { name, value, span }),
+                        ASTNode::Index { optional: true, span, .. } => Err(Diagnostic::error(SYNTAX_ERROR, "Cannot assign through '?.'", span)
+                            .with_label("cannot assign to this")
+                            .with_help("use '.' or '[...]' to assign to a key")),
+                        ASTNode::Index { object, index, .. } => Ok(ASTNode::IndexAssignment { object, index, value, span }),
+                        other => Err(Diagnostic::error(SYNTAX_ERROR, "Invalid assignment target", other.span())
+                            .with_label("cannot assign to this")),
+                    };
+                } else if let ASTNode::Call { .. } = target {
+                    return Ok(target); // A function call used as a statement
                 } else {
-                    return Err("Expected '=' after identifier for assignment.".to_string());
+                    return Err(self.error("Expected '=' after identifier for assignment.")
+                        .with_help(format!("to assign a value write `{} = <value>`", var_name))
+                        .with_help(format!("to call a function write `{}(<arguments>)`", var_name)));
                 }
             }
         }
 
-        Err("Expected statement or identifier".to_string())
+        Err(self.error("Expected statement or identifier"))
     }
 
-    fn parse_show_statement(&mut self) -> Result<ASTNode, String> {
-        self.next_token(); // Move past 'show'
-        
-        // Check if the next token is an open parenthesis
-        if let Some(ref token) = self.current_token {
-            if token.token_type == TokenType::OpenParen {
-                self.next_token(); // Skip '('
-                let value_node = self.parse_expression(0)?; // Parse the expression inside the parentheses
-                
-                // Ensure we have a closing parenthesis
-                if let Some(Token { token_type: TokenType::CloseParen, .. }) = self.current_token {
-                    self.next_token(); // Skip ')'
-                    return Ok(ASTNode::ShowStatement {
-                        value: Box::new(value_node),
-                    });
-                } else {
-                    return Err("Expected ')' after expression".to_string());
-                }
-            }
-        }
-        
-        // If there's no open parenthesis, parse the expression directly
+    fn parse_show_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.next_token()?; // Move past 'show'
+
+        // `show (a)` is an ordinary grouped expression, so `show (1 + 2) * 3` works
         let value_node = self.parse_expression(0)?;
-        
+
         Ok(ASTNode::ShowStatement {
+            span: start.to(value_node.span()),
             value: Box::new(value_node),
         })
     }
 
-    fn get_precedence(&self, token_type: &TokenType) -> i32 {
-        match token_type {
+    // Binding power of an infix operator, from loosest to tightest.
+    // Returns 0 for tokens that can't continue an expression.
+    fn get_precedence(token: &Token) -> i32 {
+        match token.token_type {
+            TokenType::NullCoalesce => 1,
+            TokenType::LogicalOr => 2,
+            TokenType::LogicalAnd => 3,
+            TokenType::EqualEquals | TokenType::NotEquals => 4,
             TokenType::BinaryOperator => {
-                match &self.current_token.as_ref().unwrap().value[..] {
-                    "+" | "-" => 1,               // Lowest precedence for addition and subtraction
-                    "^" => 2,                     // Medium precedence for bitwise XOR
-                    "*" | "/" | "%" => 3,         // Higher precedence for multiplication, division, and modulo
-                    "**" => 4,                    // Highest precedence for exponentiation
+                match &token.value[..] {
+                    "<" | ">" | "<=" | ">=" => 5, // Comparisons
+                    "+" | "-" => 6,               // Addition and subtraction
+                    "^" => 7,                     // Bitwise XOR
+                    "*" | "/" | "//" | "%" => 8,  // Multiplication, division, and modulo
+                    "**" => 10,                   // Exponentiation, binds tighter than unary minus
                     _ => 0,                       // Default precedence for unknown or unhandled operators
                 }
             }
@@ -240,76 +609,627 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_expression(&mut self, precedence: i32) -> Result<ASTNode, String> {
-        let mut left = self.parse_primary()?; // Parse the left operand
-    
+    fn parse_expression(&mut self, precedence: i32) -> Result<ASTNode, Diagnostic> {
+        let mut left = self.nested(Self::parse_unary)?; // Parse the left operand
+
         while let Some(ref token) = self.current_token {
-            let token_precedence = self.get_precedence(&token.token_type);
-            
-            if token_precedence < precedence {
+            let token_precedence = Self::get_precedence(token);
+
+            if token_precedence == 0 || token_precedence < precedence {
                 break; // Stop if the current operator has lower precedence
             }
-    
-            // If we have a binary operator, parse it
-            if token.token_type == TokenType::BinaryOperator {
-                let operator = token.value.clone();
-                self.next_token(); // Move past the operator
-                let right = self.parse_expression(token_precedence + 1)?; // Parse the right operand
-                left = ASTNode::BinaryOperation {
-                    left: Box::new(left),
-                    operator,
-                    right: Box::new(right),
-                };
-            } else {
-                break;
-            }
+
+            let operator = token.value.clone();
+            // `**` is right-associative, so its right operand may hold another `**`
+            let next_precedence = if operator == "**" { token_precedence } else { token_precedence + 1 };
+            self.next_token()?; // Move past the operator
+            let right = self.parse_expression(next_precedence)?; // Parse the right operand
+            left = ASTNode::BinaryOperation {
+                span: left.span().to(right.span()),
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            };
         }
-    
+
         Ok(left)
     }
 
-    fn parse_primary(&mut self) -> Result<ASTNode, String> {
-        if let Some(token) = self.current_token.take() {
+    // Run `parse` one nesting level deeper, failing once the input nests
+    // past `MAX_NESTING` instead of overflowing the stack
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, Diagnostic>) -> Result<T, Diagnostic> {
+        if self.nesting >= MAX_NESTING {
+            return Err(self.error(format!("Nesting is too deep, the limit is {} levels", MAX_NESTING))
+                .with_label("nested too deeply")
+                .with_help("split the expression up using variables or functions"));
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    // Prefix `!` and `-`. They bind tighter than every binary operator
+    // except `**`, so `-2 ** 2` is `-(2 ** 2)`.
+    fn parse_unary(&mut self) -> Result<ASTNode, Diagnostic> {
+        let token = match self.current_token {
+            Some(ref token) => token.clone(),
+            None => return self.parse_primary(),
+        };
+
+        let is_unary = token.token_type == TokenType::Bang
+            || (token.token_type == TokenType::BinaryOperator && token.value == "-");
+        if !is_unary {
+            let primary = self.parse_primary()?;
+            return self.parse_postfix(primary);
+        }
+
+        self.next_token()?; // Move past the operator
+        let operand = self.parse_expression(UNARY_PRECEDENCE)?;
+        Ok(ASTNode::UnaryOperation {
+            span: token.span.to(operand.span()),
+            operator: token.value,
+            operand: Box::new(operand),
+        })
+    }
+
+    fn parse_primary(&mut self) -> Result<ASTNode, Diagnostic> {
+        if let Some(token) = self.current_token.clone() {
+            let span = token.span;
             match token.token_type {
                 TokenType::Number => {
-                    self.next_token(); // Advance the token
-                    let num_value: f64 = token.value.parse().map_err(|_| "Invalid number format")?; 
-                    Ok(ASTNode::ValueNum { value: num_value })
+                    self.next_token()?; // Advance the token
+                    parse_number(&token.value, span).ok_or_else(|| {
+                        Diagnostic::error(SYNTAX_ERROR, "Invalid number format", span)
+                    })
                 }
                 TokenType::String => {
-                    self.next_token(); // Advance the token
-                    Ok(ASTNode::Value { value: token.value })
+                    self.next_token()?; // Advance the token
+                    Ok(ASTNode::Value { value: token.value, span })
                 }
                 TokenType::BooleanLiteral => {
                     let bool_value = match token.value.as_str() {
                         "true" => true,
                         "false" => false,
-                        _ => return Err("Invalid boolean literal".to_string()),
+                        _ => {
+                            return Err(Diagnostic::error(SYNTAX_ERROR, "Invalid boolean literal", span));
+                        }
                     };
-                    Ok(ASTNode::ValueBool { value: bool_value })
+                    self.next_token()?; // Advance the token
+                    Ok(ASTNode::ValueBool { value: bool_value, span })
                 },
                 TokenType::Identifier => {
-                    self.next_token(); // Advance the token
-                    Ok(ASTNode::Variable { name: token.value })
+                    self.next_token()?; // Advance the token
+                    Ok(ASTNode::Variable { name: token.value, span })
+                }
+                TokenType::Func => self.parse_function_expression(),
+                TokenType::OpenBracket => self.parse_array_literal(),
+                TokenType::OpenBrace => self.parse_object_literal(),
+                TokenType::ArrSize => {
+                    self.next_token()?; // Skip 'arrsize'
+                    let array = self.parse_unary()?;
+                    Ok(ASTNode::ArraySize { span: span.to(array.span()), array: Box::new(array) })
                 }
+                TokenType::UpperCase => {
+                    self.next_token()?; // Skip 'uppercase'
+                    let text = self.parse_unary()?;
+                    Ok(ASTNode::Uppercase { span: span.to(text.span()), expr: Box::new(text) })
+                }
+                TokenType::LowerCase => {
+                    self.next_token()?; // Skip 'lowercase'
+                    let text = self.parse_unary()?;
+                    Ok(ASTNode::Lowercase { span: span.to(text.span()), expr: Box::new(text) })
+                }
+                TokenType::ToJson | TokenType::PrettyJson => {
+                    let pretty = token.token_type == TokenType::PrettyJson;
+                    self.next_token()?; // Skip 'tojson' or 'prettyjson'
+                    let value = self.parse_unary()?;
+                    Ok(ASTNode::ToJson { span: span.to(value.span()), value: Box::new(value), pretty })
+                }
+                TokenType::Void => {
+                    self.next_token()?; // Skip 'void'
+                    Ok(ASTNode::Void { span })
+                }
+                TokenType::IsVoid => {
+                    self.next_token()?; // Skip 'isvoid'
+                    let value = self.parse_unary()?;
+                    Ok(ASTNode::IsVoid { span: span.to(value.span()), value: Box::new(value) })
+                }
+                TokenType::ParJson => {
+                    self.next_token()?; // Skip 'parjson'
+                    let text = self.parse_unary()?;
+                    Ok(ASTNode::ParseJson { span: span.to(text.span()), text: Box::new(text) })
+                }
+                TokenType::Arr => {
+                    self.next_token()?; // Skip 'arr'
+                    match self.parse_unary()? {
+                        ASTNode::Index { object, index, optional, span: end } => {
+                            Ok(ASTNode::Index { object, index, optional, span: span.to(end) })
+                        }
+                        other => Err(Diagnostic::error(SYNTAX_ERROR, "Expected an indexed array after 'arr'", other.span())
+                            .with_help("write `arr name[index]`")),
+                    }
+                }
+                TokenType::Call => {
+                    self.next_token()?; // Skip 'call'
+                    let callee = match self.current_token.clone() {
+                        Some(Token { token_type: TokenType::Identifier, value, span, .. }) => {
+                            self.next_token()?; // Move past the function name
+                            ASTNode::Variable { name: value, span }
+                        }
+                        _ => {
+                            return Err(self.error("Expected a function name after 'call'")
+                                .with_help("write `call name(<arguments>)`"));
+                        }
+                    };
+                    match self.parse_postfix(callee)? {
+                        call @ ASTNode::Call { .. } => Ok(call),
+                        _ => Err(self.error("Expected '(' after the function name")
+                            .with_help("write `call name(<arguments>)`")),
+                    }
+                }
+                TokenType::TemplateStart => self.parse_template(),
                 TokenType::OpenParen => {
-                    self.next_token(); // Skip '('
+                    self.next_token()?; // Skip '('
                     let expr = self.parse_expression(0)?; // Recursively parse inner expression
                     if let Some(Token { token_type: TokenType::CloseParen, .. }) = self.current_token {
-                        self.next_token(); // Skip ')'
+                        self.next_token()?; // Skip ')'
                         Ok(expr)
                     } else {
-                        Err("Expected ')'".to_string())
+                        Err(self.error("Expected ')'")
+                            .with_label("expected ')'")
+                            .with_secondary(span, "unclosed '(' opened here"))
                     }
                 }
-                _ => Err("Unexpected token in primary expression".to_string()),
+                _ => Err(Diagnostic::error(SYNTAX_ERROR, "Unexpected token in primary expression", span)
+                    .with_label(format!("unexpected '{}'", token.value))),
             }
         } else {
-            Err("Unexpected end of input in primary expression".to_string())
+            Err(self.error("Unexpected end of input in primary expression"))
         }
     }
 
-    fn next_token(&mut self) {
-        self.current_token = self.lexer.next_token();
+    // Calls and indexing written after an expression, `makeAdder(1)(2)` or
+    // `grid[1][2]`. Everything after a `?.` is optional too, so a void part
+    // way along makes the whole chain void.
+    fn parse_postfix(&mut self, mut expr: ASTNode) -> Result<ASTNode, Diagnostic> {
+        let mut optional = false;
+        loop {
+            match self.current_token {
+                Some(Token { token_type: TokenType::OpenParen, .. }) => {
+                    expr = self.parse_call_arguments(expr, optional)?;
+                }
+                Some(Token { token_type: TokenType::OpenBracket, .. }) => {
+                    expr = self.parse_index(expr, optional)?;
+                }
+                Some(Token { token_type: TokenType::Dot, .. }) => {
+                    self.next_token()?; // Skip '.'
+                    expr = self.parse_key(expr, optional)?;
+                }
+                Some(Token { token_type: TokenType::QuestionDot, .. }) => {
+                    self.next_token()?; // Skip '?.'
+                    optional = true;
+                    expr = match self.current_token {
+                        Some(Token { token_type: TokenType::OpenBracket, .. }) => self.parse_index(expr, true)?,
+                        _ => self.parse_key(expr, true)?,
+                    };
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    // Parse `[index]` after `object`
+    fn parse_index(&mut self, object: ASTNode, optional: bool) -> Result<ASTNode, Diagnostic> {
+        let open_span = self.current_span();
+        self.next_token()?; // Skip '['
+        let index = self.parse_expression(0)?;
+        match self.current_token {
+            Some(Token { token_type: TokenType::CloseBracket, span, .. }) => {
+                self.next_token()?; // Skip ']'
+                Ok(ASTNode::Index { span: object.span().to(span), object: Box::new(object), index: Box::new(index), optional })
+            }
+            _ => Err(self.error("Expected ']' after the index")
+                .with_label("expected ']'")
+                .with_secondary(open_span, "unclosed '[' opened here")),
+        }
+    }
+
+    // Parse the key name after `object.` or `object?.`
+    fn parse_key(&mut self, object: ASTNode, optional: bool) -> Result<ASTNode, Diagnostic> {
+        match self.current_token.clone() {
+            Some(Token { token_type: TokenType::Identifier, value, span, .. }) => {
+                self.next_token()?; // Move past the key
+                let key = ASTNode::Value { value, span };
+                Ok(ASTNode::Index { span: object.span().to(span), object: Box::new(object), index: Box::new(key), optional })
+            }
+            _ => Err(self.error("Expected a key name after '.'")
+                .with_help("keys that aren't plain names can be read with `object[\"key\"]`")),
+        }
+    }
+
+    // Parse `[a, b, c]`, a trailing comma is allowed
+    fn parse_array_literal(&mut self) -> Result<ASTNode, Diagnostic> {
+        let open_span = self.current_span();
+        self.next_token()?; // Skip '['
+
+        let mut elements = Vec::new();
+        loop {
+            match self.current_token {
+                Some(Token { token_type: TokenType::CloseBracket, span, .. }) => {
+                    self.next_token()?; // Skip ']'
+                    return Ok(ASTNode::ArrayLiteral { elements, span: open_span.to(span) });
+                }
+                None => {
+                    return Err(self.error("Expected ']' to close the array")
+                        .with_secondary(open_span, "array starts here"));
+                }
+                _ => {}
+            }
+
+            elements.push(self.parse_expression(0)?);
+            match self.current_token {
+                Some(Token { token_type: TokenType::Comma, .. }) => self.next_token()?, // Skip ','
+                Some(Token { token_type: TokenType::CloseBracket, .. }) => {}
+                _ => {
+                    return Err(self.error("Expected ',' or ']' after the array element")
+                        .with_secondary(open_span, "array starts here"));
+                }
+            }
+        }
+    }
+
+    // Parse `{ "key": value, key2: value }`, a trailing comma is allowed
+    fn parse_object_literal(&mut self) -> Result<ASTNode, Diagnostic> {
+        let open_span = self.current_span();
+        self.next_token()?; // Skip '{'
+
+        let mut entries = Vec::new();
+        loop {
+            let key = match self.current_token.clone() {
+                Some(Token { token_type: TokenType::CloseBrace, span, .. }) => {
+                    self.next_token()?; // Skip '}'
+                    return Ok(ASTNode::ObjectLiteral { entries, span: open_span.to(span) });
+                }
+                Some(Token { token_type: TokenType::String | TokenType::Identifier, value, .. }) => value,
+                _ => {
+                    return Err(self.error("Expected a key or '}' in the object")
+                        .with_secondary(open_span, "object starts here")
+                        .with_help("keys are names or strings"));
+                }
+            };
+            self.next_token()?; // Move past the key
+            self.expect(TokenType::Colon, "':' after the object key")?;
+            entries.push((key, self.parse_expression(0)?));
+
+            match self.current_token {
+                Some(Token { token_type: TokenType::Comma, .. }) => self.next_token()?, // Skip ','
+                Some(Token { token_type: TokenType::CloseBrace, .. }) => {}
+                _ => {
+                    return Err(self.error("Expected ',' or '}' after the object value")
+                        .with_secondary(open_span, "object starts here"));
+                }
+            }
+        }
+    }
+
+    fn parse_delkey_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.next_token()?; // Move past 'delkey'
+
+        match self.parse_unary()? {
+            ASTNode::Index { object, index, span, .. } => Ok(ASTNode::DeleteKey { object, key: index, span: start.to(span) }),
+            other => Err(Diagnostic::error(SYNTAX_ERROR, "Expected a key to delete after 'delkey'", other.span())
+                .with_help("write `delkey object.key` or `delkey object[\"key\"]`")),
+        }
+    }
+
+    // Parse `arradd array value` and `arrdel array value`
+    fn parse_array_change(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        let is_add = matches!(self.current_token, Some(Token { token_type: TokenType::ArrAdd, .. }));
+        self.next_token()?; // Move past 'arradd' or 'arrdel'
+
+        // Only a prefix expression, so the value after it isn't read as part of it
+        let array = Box::new(self.parse_unary()?);
+        let value = self.parse_expression(0)?;
+        let span = start.to(value.span());
+        let value = Box::new(value);
+
+        Ok(if is_add {
+            ASTNode::ArrayAdd { array, value, span }
+        } else {
+            ASTNode::ArrayDelete { array, value, span }
+        })
+    }
+
+    // Parse a backtick string: `text ${expression} more text`
+    fn parse_template(&mut self) -> Result<ASTNode, Diagnostic> {
+        let start = self.current_span();
+        self.next_token()?; // Skip the opening backtick
+
+        let mut parts = Vec::new();
+        loop {
+            let token = match self.current_token.clone() {
+                Some(token) => token,
+                None => return Err(self.error("Unterminated template string")),
+            };
+
+            match token.token_type {
+                TokenType::TemplateString => {
+                    self.next_token()?;
+                    parts.push(ASTNode::Value { value: token.value, span: token.span });
+                }
+                TokenType::TemplateExprStart => {
+                    self.next_token()?; // Skip '${'
+                    parts.push(self.parse_expression(0)?);
+                    if let Some(Token { token_type: TokenType::TemplateExprEnd, .. }) = self.current_token {
+                        self.next_token()?; // Skip '}'
+                    } else {
+                        return Err(self.error("Expected '}' to close the template expression")
+                            .with_label("expected '}'")
+                            .with_secondary(token.span, "interpolation starts here"));
+                    }
+                }
+                TokenType::TemplateEnd => {
+                    self.next_token()?; // Skip the closing backtick
+                    return Ok(ASTNode::Template { parts, span: start.to(token.span) });
+                }
+                _ => return Err(self.error("Unexpected token in template string")),
+            }
+        }
+    }
+
+    fn warn_detached_docs(&mut self) {
+        let spans: Vec<Span> = match self.current_token {
+            Some(ref token) => token.trivia.iter()
+                .map(|trivia| match trivia {
+                    Trivia::DocComment { span, .. } => *span,
+                })
+                .collect(),
+            None => return,
+        };
+        if let (Some(first), Some(last)) = (spans.first(), spans.last()) {
+            self.errors.push(
+                Diagnostic::warning("W0100", "Doc comment is not attached to a declaration", first.to(*last))
+                    .with_help("doc comments describe the `let`, `make` or `func` right below them, use `//` for a plain comment"),
+            );
+        }
+    }
+
+    // Doc comments written right above the current token, joined into one text
+    fn current_doc(&self) -> Option<String> {
+        let token = self.current_token.as_ref()?;
+        let lines: Vec<&str> = token.trivia.iter()
+            .map(|trivia| match trivia {
+                Trivia::DocComment { text, .. } => text.as_str(),
+            })
+            .collect();
+        if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        }
+    }
+
+    // Span of the token being looked at. Once the input runs out this is the
+    // point right after the last token, so errors don't land on trailing blank lines.
+    fn current_span(&self) -> Span {
+        match (&self.current_token, self.previous_span) {
+            (Some(token), _) => token.span,
+            (None, Some(last)) => {
+                let text = &self.lexer.source()[last.start..last.end];
+                let (line, column) = match text.rfind('\n') {
+                    Some(i) => (last.line + text.matches('\n').count(), text[i + 1..].chars().count() + 1),
+                    None => (last.line, last.column + text.chars().count()),
+                };
+                Span::new(last.end, last.end, line, column)
+            }
+            (None, None) => self.lexer.position(),
+        }
+    }
+
+    // Skip a token of the given type, or fail with "Expected <what>"
+    fn expect(&mut self, token_type: TokenType, what: &str) -> Result<Span, Diagnostic> {
+        match self.current_token {
+            Some(ref token) if token.token_type == token_type => {
+                let span = token.span;
+                self.next_token()?;
+                Ok(span)
+            }
+            _ => Err(self.error(format!("Expected {}", what))),
+        }
+    }
+
+    fn expect_identifier(&mut self, what: &str) -> Result<String, Diagnostic> {
+        match self.current_token {
+            Some(ref token) if token.token_type == TokenType::Identifier => {
+                let name = token.value.clone();
+                self.next_token()?;
+                Ok(name)
+            }
+            // A keyword where a name should be, like `let arr = ...`
+            Some(Token { ref token_type, ref value, .. })
+                if !matches!(token_type, TokenType::String | TokenType::TemplateString)
+                    && value.starts_with(|c: char| c.is_alphabetic() || c == '_') =>
+            {
+                Err(self.error(format!("Expected {}, found the keyword '{}'", what, value))
+                    .with_label("reserved word")
+                    .with_help("keywords can't be used as names, pick another one"))
+            }
+            _ => Err(self.error(format!("Expected {}", what))),
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::error(SYNTAX_ERROR, message, self.current_span())
+    }
+
+    fn next_token(&mut self) -> Result<(), Diagnostic> {
+        let token = self.lexer.next_token()?;
+        self.replace_current(token);
+        Ok(())
+    }
+
+    // Like `next_token`, but records lexer errors and keeps going until it
+    // finds a token it can use
+    fn advance_recovering(&mut self) {
+        loop {
+            match self.lexer.next_token() {
+                Ok(token) => {
+                    self.replace_current(token);
+                    return;
+                }
+                Err(error) => self.errors.push(error.into()),
+            }
+        }
+    }
+
+    fn replace_current(&mut self, token: Option<Token>) {
+        if let Some(previous) = self.current_token.take() {
+            match previous.token_type {
+                TokenType::OpenBrace => self.brace_depth += 1,
+                TokenType::CloseBrace => self.brace_depth = self.brace_depth.saturating_sub(1),
+                _ => {}
+            }
+            self.previous_span = Some(previous.span);
+        }
+        self.current_token = token;
+    }
+}
+
+// Turn a number token into its value. Literals with a fraction or an
+// exponent are floats, the rest are integers of any size. The lexer has
+// already checked the digits.
+fn parse_number(literal: &str, span: Span) -> Option<ASTNode> {
+    let (digits, radix) = match literal.get(..2) {
+        Some("0x") => (&literal[2..], 16),
+        Some("0b") => (&literal[2..], 2),
+        Some("0o") => (&literal[2..], 8),
+        _ if literal.contains(['.', 'e']) => return literal.parse().ok().map(|value| ASTNode::ValueFloat { value, span }),
+        _ => (literal, 10),
+    };
+    let value = BigInt::parse_bytes(digits.as_bytes(), radix)?;
+    Some(match value.to_i64() {
+        Some(value) => ASTNode::ValueInt { value, span },
+        None => ASTNode::ValueBigInt { value, span },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Vec<ASTNode> {
+        let (statements, errors) = Parser::new(Lexer::new(input)).parse();
+        assert!(errors.is_empty(), "{:?} should parse: {:?}", input, errors);
+        statements
+    }
+
+    #[test]
+    fn nodes_carry_spans() {
+        let statements = parse("let total = 1 + 22\nshow total");
+        let spans: Vec<(usize, usize, usize, usize)> = statements.iter()
+            .map(|node| node.span())
+            .map(|span| (span.start, span.end, span.line, span.column))
+            .collect();
+        assert_eq!(spans, [(0, 18, 1, 1), (19, 29, 2, 1)]);
+        match &statements[0] {
+            ASTNode::VariableDeclaration { value, .. } => assert_eq!((value.span().start, value.span().end), (12, 18)),
+            other => panic!("expected a declaration, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_every_error_and_keeps_going() {
+        let (statements, errors) = Parser::new(Lexer::new("let a = 1\nlet = 2\nshow @\nlet b = 3\nshow )")).parse();
+        let messages: Vec<(&str, usize)> = errors.iter().map(|e| (e.message.as_str(), e.span().line)).collect();
+        assert_eq!(messages, [
+            ("Expected variable name after 'let' or 'make'", 2),
+            ("Unexpected character: '@'", 3),
+            ("Unexpected token in primary expression", 5),
+        ]);
+        let declared: Vec<&str> = statements.iter()
+            .filter_map(|node| match node {
+                ASTNode::VariableDeclaration { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(declared, ["a", "b"]);
+    }
+
+    // The operators of a binary expression, outermost first along the left side
+    fn left_operators(node: &ASTNode) -> Vec<&str> {
+        match node {
+            ASTNode::BinaryOperation { left, operator, .. } => {
+                let mut operators = vec![operator.as_str()];
+                operators.extend(left_operators(left));
+                operators
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn show_with_parenthesized_operand() {
+        let statements = parse("show (1 + 2) * 3");
+        match &statements[..] {
+            [ASTNode::ShowStatement { value, .. }] => assert_eq!(left_operators(value), ["*", "+"]),
+            other => panic!("expected one show statement, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn show_with_whole_expression_in_parentheses() {
+        let statements = parse("show (1 + 2)\nshow 4");
+        assert_eq!(statements.len(), 2);
+        match &statements[0] {
+            ASTNode::ShowStatement { value, .. } => assert_eq!(left_operators(value), ["+"]),
+            other => panic!("expected a show statement, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn show_with_unclosed_parenthesis() {
+        let (_, errors) = Parser::new(Lexer::new("show (1 + 2")).parse();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Expected ')'");
+    }
+
+    #[test]
+    fn double_slash_after_an_operand() {
+        let statements = parse("let x = 1 + 10 // 3 * 2; // a comment\nshow x");
+        match &statements[..] {
+            [ASTNode::VariableDeclaration { value, .. }, ASTNode::ShowStatement { .. }] => {
+                assert_eq!(left_operators(value), ["+"]);
+            }
+            other => panic!("expected a declaration and a show, found {:?}", other),
+        }
+        let (_, errors) = Parser::new(Lexer::new("show x // a comment")).parse();
+        assert!(!errors.is_empty());
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let inputs = [
+            format!("show {}1{}", "(".repeat(3000), ")".repeat(3000)),
+            format!("show {}1", "-".repeat(20000)),
+            format!("{}{}", "if true {\n".repeat(300), "}\n".repeat(300)),
+        ];
+        for input in inputs {
+            let errors = crate::interpreter::tests::on_main_stack(move || Parser::new(Lexer::new(&input)).parse().1);
+            let messages: Vec<(&str, &str)> = errors.iter().map(|e| (e.code, e.message.as_str())).collect();
+            assert_eq!(messages[0], ("E0100", "Nesting is too deep, the limit is 200 levels"));
+        }
+        crate::interpreter::tests::on_main_stack(|| parse(&format!("show {}1{}", "(".repeat(150), ")".repeat(150))));
+    }
+
+    #[test]
+    fn keywords_are_not_names() {
+        for (input, keyword) in [("let arr = [1]", "arr"), ("make show = 1", "show"), ("func if() { }", "if")] {
+            let (_, errors) = Parser::new(Lexer::new(input)).parse();
+            assert!(errors[0].message.ends_with(&format!("found the keyword '{}'", keyword)), "{:?}", errors);
+        }
+        parse("let a = [1]\narr a[0]");
     }
 }
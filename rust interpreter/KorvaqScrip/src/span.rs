@@ -0,0 +1,37 @@
+use std::fmt;
+
+// A region of the source text.
+// `start` and `end` are byte offsets into the input, `line` and `column` are
+// 1-based and describe where the region starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Span { start, end, line, column }
+    }
+
+    // Build a span that covers both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        if other.start < self.start {
+            return other.to(self);
+        }
+        Span {
+            start: self.start,
+            end: self.end.max(other.end),
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
@@ -0,0 +1,233 @@
+use crate::interpreter::{Function, Interpreter, RuntimeError, Value};
+use crate::span::Span;
+use std::cell::RefCell;
+use std::rc::Rc;
+use unicode_segmentation::UnicodeSegmentation;
+
+// The string library. Positions and lengths count grapheme clusters, what a
+// reader sees as one character, so "e\u{301}" has length 1 and slicing or
+// reversing never splits an accent or an emoji from what it belongs to.
+
+// Largest string `repeat` and `pad` will build, in bytes, so a typo in a
+// count can't eat all the memory
+const MAX_RESULT_BYTES: usize = 1 << 24;
+
+pub fn add_builtins(interpreter: &mut Interpreter) {
+    interpreter.add_builtin(Function::builtin("reverse", &["text"], reverse));
+    interpreter.add_builtin(Function::builtin("tokenize", &["text"], tokenize));
+    interpreter.add_builtin(Function::builtin("trim", &["text"], trim));
+    interpreter.add_builtin(Function::builtin("split", &["text", "separator"], split));
+    interpreter.add_builtin(Function::builtin("join", &["array", "separator"], join));
+    interpreter.add_builtin(Function::builtin("replace", &["text", "from", "to"], replace));
+    interpreter.add_builtin(Function::builtin("contains", &["text", "part"], contains));
+    interpreter.add_builtin(Function::builtin("starts_with", &["text", "prefix"], starts_with));
+    interpreter.add_builtin(Function::builtin("substring", &["text", "start", "end"], substring));
+    interpreter.add_builtin(Function::builtin("len", &["value"], len));
+    interpreter.add_builtin(Function::builtin("repeat", &["text", "count"], repeat));
+    interpreter.add_builtin(Function::builtin("pad", &["text", "width"], pad));
+}
+
+// `uppercase text` and `lowercase text`
+pub fn change_case(value: Value, upper: bool, span: Span) -> Result<Value, RuntimeError> {
+    match value {
+        Value::String(text) if upper => Ok(Value::String(text.to_uppercase())),
+        Value::String(text) => Ok(Value::String(text.to_lowercase())),
+        other => {
+            let keyword = if upper { "uppercase" } else { "lowercase" };
+            Err(RuntimeError::new(format!("{} expects a string, found {}", keyword, other.describe()), span).with_kind("TypeError"))
+        }
+    }
+}
+
+// `reverse(text)`
+fn reverse(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments, 0, "reverse", span)?;
+    Ok(Value::String(text.graphemes(true).rev().collect()))
+}
+
+// `tokenize(text)`: the words of `text`, split on any run of whitespace
+fn tokenize(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments, 0, "tokenize", span)?;
+    Ok(string_array(text.split_whitespace()))
+}
+
+// `trim(text)`
+fn trim(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments, 0, "trim", span)?;
+    Ok(Value::String(text.trim().to_string()))
+}
+
+// `split(text, separator)`, an empty separator splits into single characters
+fn split(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments, 0, "split", span)?;
+    let separator = string_argument(&arguments, 1, "split", span)?;
+    if separator.is_empty() {
+        Ok(string_array(text.graphemes(true)))
+    } else {
+        Ok(string_array(text.split(separator)))
+    }
+}
+
+// `join(array, separator)`, elements that aren't strings are written the way `show` would
+fn join(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let elements = match &arguments[0] {
+        Value::Array(elements) => elements,
+        other => return Err(RuntimeError::new(format!("join expects an array as argument 1, found {}", other.describe()), span).with_kind("TypeError")),
+    };
+    let separator = string_argument(&arguments, 1, "join", span)?;
+    let parts: Vec<String> = elements.borrow().iter().map(|element| element.to_string()).collect();
+    Ok(Value::String(parts.join(separator)))
+}
+
+// `replace(text, from, to)`: every `from` in `text` becomes `to`
+fn replace(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments, 0, "replace", span)?;
+    let from = string_argument(&arguments, 1, "replace", span)?;
+    let to = string_argument(&arguments, 2, "replace", span)?;
+    if from.is_empty() {
+        return Err(RuntimeError::new("replace cannot search for an empty string", span).with_kind("ValueError"));
+    }
+    Ok(Value::String(text.replace(from, to)))
+}
+
+// `contains(text, part)`
+fn contains(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments, 0, "contains", span)?;
+    let part = string_argument(&arguments, 1, "contains", span)?;
+    Ok(Value::Boolean(text.contains(part)))
+}
+
+// `starts_with(text, prefix)`
+fn starts_with(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments, 0, "starts_with", span)?;
+    let prefix = string_argument(&arguments, 1, "starts_with", span)?;
+    Ok(Value::Boolean(text.starts_with(prefix)))
+}
+
+// `substring(text, start, end)`: the characters from `start` up to, but not
+// including, `end`, the same as a `start..end` range
+fn substring(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments, 0, "substring", span)?;
+    let graphemes: Vec<&str> = text.graphemes(true).collect();
+    let start = whole_number_argument(&arguments, 1, "substring", span)?;
+    let end = whole_number_argument(&arguments, 2, "substring", span)?;
+    for position in [start, end] {
+        if position < 0 || position as usize > graphemes.len() {
+            return Err(RuntimeError::new(
+                format!("Position {} is out of bounds for a string of length {}", position, graphemes.len()),
+                span,
+            ).with_kind("IndexError"));
+        }
+    }
+    if start > end {
+        return Err(RuntimeError::new(format!("substring start {} is after its end {}", start, end), span).with_kind("ValueError"));
+    }
+    Ok(Value::String(graphemes[start as usize..end as usize].concat()))
+}
+
+// `len(value)`: characters in a string, elements in an array or keys in an object
+fn len(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let length = match &arguments[0] {
+        Value::String(text) => text.graphemes(true).count(),
+        Value::Array(elements) => elements.borrow().len(),
+        Value::Object(entries) => entries.borrow().len(),
+        other => return Err(RuntimeError::new(format!("len expects a string, array or object, found {}", other.describe()), span).with_kind("TypeError")),
+    };
+    Ok(Value::Int(length as i64))
+}
+
+// `repeat(text, count)`
+fn repeat(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments, 0, "repeat", span)?;
+    let count = whole_number_argument(&arguments, 1, "repeat", span)?;
+    if count < 0 {
+        return Err(RuntimeError::new(format!("repeat count cannot be negative, found {}", count), span).with_kind("ValueError"));
+    }
+    if text.len().saturating_mul(count as usize) > MAX_RESULT_BYTES {
+        return Err(too_large("repeat", span));
+    }
+    Ok(Value::String(text.repeat(count as usize)))
+}
+
+// `pad(text, width)`: spaces are added until `text` is `width` characters
+// long. Like printf's `%5s` and `%-5s`, a positive width pads on the left and
+// a negative one on the right. Longer text is left as it is.
+fn pad(_: &mut Interpreter, arguments: Vec<Value>, span: Span) -> Result<Value, RuntimeError> {
+    let text = string_argument(&arguments, 0, "pad", span)?;
+    let width = whole_number_argument(&arguments, 1, "pad", span)?;
+    if width.unsigned_abs() > MAX_RESULT_BYTES as u64 {
+        return Err(too_large("pad", span));
+    }
+    let missing = (width.unsigned_abs() as usize).saturating_sub(text.graphemes(true).count());
+    let spaces = " ".repeat(missing);
+    if width >= 0 {
+        Ok(Value::String(spaces + text))
+    } else {
+        Ok(Value::String(text.to_string() + &spaces))
+    }
+}
+
+fn too_large(function: &str, span: Span) -> RuntimeError {
+    RuntimeError::new(format!("The result of {} would be larger than {} bytes", function, MAX_RESULT_BYTES), span).with_kind("ValueError")
+}
+
+fn string_argument<'a>(arguments: &'a [Value], index: usize, function: &str, span: Span) -> Result<&'a str, RuntimeError> {
+    match &arguments[index] {
+        Value::String(text) => Ok(text),
+        other => Err(RuntimeError::new(
+            format!("{} expects a string as argument {}, found {}", function, index + 1, other.describe()),
+            span,
+        ).with_kind("TypeError")),
+    }
+}
+
+fn whole_number_argument(arguments: &[Value], index: usize, function: &str, span: Span) -> Result<i64, RuntimeError> {
+    match arguments[index] {
+        Value::Int(n) => Ok(n),
+        ref other => Err(RuntimeError::new(
+            format!("{} expects an integer as argument {}, found {}", function, index + 1, other.describe()),
+            span,
+        ).with_kind("TypeError")),
+    }
+}
+
+fn string_array<'a>(parts: impl Iterator<Item = &'a str>) -> Value {
+    Value::Array(Rc::new(RefCell::new(parts.map(|part| Value::String(part.to_string())).collect())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::tests::{error_message, evaluate};
+
+    #[test]
+    fn argument_errors_name_the_type() {
+        assert_eq!(error_message("reverse(1.5)", "TypeError"), "reverse expects a string as argument 1, found float 1.5");
+        assert_eq!(error_message("repeat(\"a\", \"2\")", "TypeError"), "repeat expects an integer as argument 2, found string \"2\"");
+        assert_eq!(error_message("join(void, \",\")", "TypeError"), "join expects an array as argument 1, found void");
+        assert_eq!(error_message("len(true)", "TypeError"), "len expects a string, array or object, found boolean true");
+    }
+
+    #[test]
+    fn positions_count_graphemes() {
+        assert_eq!(evaluate("len(\"e\\u{301}🎉\")").unwrap().to_string(), "2");
+        assert_eq!(evaluate("reverse(\"ae\\u{301}\")").unwrap().to_string(), "e\u{301}a");
+        assert_eq!(evaluate("substring(\"日本語\", 1, 3)").unwrap().to_string(), "本語");
+    }
+
+    #[test]
+    fn repeat_and_pad() {
+        assert_eq!(evaluate("repeat(\"ab\", 3)").unwrap().to_string(), "ababab");
+        assert_eq!(evaluate("pad(\"é\", 3)").unwrap().to_string(), "  é");
+        assert_eq!(evaluate("pad(\"é\", -3)").unwrap().to_string(), "é  ");
+        assert_eq!(error_message("repeat(\"a\", -1)", "ValueError"), "repeat count cannot be negative, found -1");
+    }
+
+    #[test]
+    fn huge_results_are_refused() {
+        let limit = "would be larger than 16777216 bytes";
+        assert_eq!(error_message("repeat(\"ab\", 9223372036854775807)", "ValueError"), format!("The result of repeat {}", limit));
+        assert_eq!(error_message("repeat(\"a\", 16777217)", "ValueError"), format!("The result of repeat {}", limit));
+        assert_eq!(error_message("pad(\"a\", 9223372036854775807)", "ValueError"), format!("The result of pad {}", limit));
+        assert_eq!(error_message("pad(\"a\", -9223372036854775807 - 1)", "ValueError"), format!("The result of pad {}", limit));
+    }
+}
@@ -5,8 +5,8 @@ pub enum TokenType {
     Let,
     Make,
     Show,
-    Boolean,
-    Equals,
+    Equals,      // `=`, assignment
+    EqualEquals, // `==` and `===`
     OpenParen,
     CloseParen,
     Colon,
@@ -18,14 +18,48 @@ pub enum TokenType {
     BinaryOperator,
     LogicalAnd,
     LogicalOr,
-    NotEquals,
+    NullCoalesce, // `??`
+    NotEquals,   // `!=` and `!==`
+    Bang,        // `!`, logical not
     Identifier,
     Number,
     String,
+    TemplateStart,     // Opening backtick
+    TemplateString,    // Literal text inside a template
+    TemplateExprStart, // `${`
+    TemplateExprEnd,   // `}` closing an interpolation
+    TemplateEnd,       // Closing backtick
     DelVar,
+    DelFunc,
+    Arr,     // `arr name[index]`
+    ArrAdd,
+    ArrDel,
+    ArrSize,
+    DelKey,  // `delkey obj.key`
+    ToJson,
+    PrettyJson, // Like `tojson`, but indented over several lines
+    ParJson,
     BooleanLiteral,
+    Void,   // The `void` literal
+    IsVoid,
     If, 
     Else, 
+    While,
+    Break,
+    Continue,
+    Loop,
+    For,
+    In,
+    DotDot, // `..` in a range, `0..5`
+    Dot,    // `.` in member access, `obj.key`
+    QuestionDot, // `?.`, member access that gives void on void
+    Func,
+    Return,
+    Call,
     UpperCase,
     LowerCase,
+    Try,
+    Catch,
+    Finally,
+    Throw,
 }
\ No newline at end of file